@@ -0,0 +1,491 @@
+use evdev::Key;
+use serde::Deserialize;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+};
+
+use crate::{keyboard::Modifier, vi::EditingMode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// The actions the keys can be bound to.
+pub enum Action {
+    /// Run the command with the entry.
+    Submit,
+    /// Exit, or kill the running command.
+    Cancel,
+    /// Delete the whole entry.
+    Clear,
+    /// Delete the character before the cursor.
+    Backspace,
+    /// Delete the character after the cursor.
+    Delete,
+    /// Move the cursor one character left.
+    Left,
+    /// Move the cursor one character right.
+    Right,
+    /// Move the cursor to the line above.
+    Up,
+    /// Move the cursor to the line below.
+    Down,
+    /// Move the cursor to the start of the line.
+    Home,
+    /// Move the cursor to the end of the line.
+    End,
+    /// Extend the selection one character left.
+    SelectLeft,
+    /// Extend the selection one character right.
+    SelectRight,
+    /// Extend the selection to the start of the line.
+    SelectHome,
+    /// Extend the selection to the end of the line.
+    SelectEnd,
+    /// Extend the selection to the start of the word before the cursor.
+    SelectWordLeft,
+    /// Extend the selection to the end of the word after the cursor.
+    SelectWordRight,
+    /// Move the cursor to the start of the word before it.
+    WordLeft,
+    /// Move the cursor to the end of the word after it.
+    WordRight,
+    /// Delete from the start of the word before the cursor.
+    WordBackspace,
+    /// Delete up to the end of the word after the cursor.
+    WordDelete,
+    /// Kill up to the end of the line.
+    KillLine,
+    /// Kill from the start of the line.
+    UnixLineDiscard,
+    /// Kill from the start of the whitespace delimited word before the cursor.
+    UnixWordRubout,
+    /// Kill up to the end of the word after the cursor.
+    KillWord,
+    /// Kill from the start of the word before the cursor.
+    BackwardKillWord,
+    /// Insert the last killed text.
+    Yank,
+    /// Replace the yanked text with the one killed before it.
+    YankPop,
+    /// Swap the characters around the cursor.
+    TransposeChars,
+    /// Insert a newline in the entry.
+    Newline,
+    /// Complete the word before the cursor, repeat to cycle through the candidates.
+    Complete,
+    /// Search the history backward, the typed text narrows the search.
+    ReverseSearch,
+    /// Add the clipboard's text to the entry and send the chord for the app to paste it.
+    Paste,
+    /// Undo the last group of edits.
+    Undo,
+    /// Redo the last undone edits.
+    Redo,
+    /// Switch to the normal mode, in the vi editing mode.
+    NormalMode,
+    /// Send the chord to the app, without editing the entry.
+    Pass,
+    /// Don't send the chord to the app.
+    Block,
+    /// Don't bind the chord, e.g. to remove a default binding.
+    None,
+}
+
+impl Action {
+    /// Whether the action adds the deleted text to the kill ring.
+    pub fn is_kill(&self) -> bool {
+        matches!(
+            self,
+            Action::KillLine
+                | Action::UnixLineDiscard
+                | Action::UnixWordRubout
+                | Action::KillWord
+                | Action::BackwardKillWord
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// What to do with the unbound chords which use Ctrl, Alt or Super.
+pub enum ShortcutPolicy {
+    /// Send them to the app, e.g. to switch windows, without editing the entry.
+    #[default]
+    Pass,
+    /// Don't send them to the app.
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+/// A key pressed along with modifiers, written as `ctrl+shift+enter`.
+pub struct Chord {
+    pub modifiers: BTreeSet<Modifier>,
+    pub key: Key,
+}
+
+impl Chord {
+    /// Create a new [`Chord`].
+    ///
+    /// # Arguments
+    ///
+    /// * `modifiers` - The modifiers to hold.
+    /// * `key` - The pressed [`Key`].
+    pub fn new(modifiers: &[Modifier], key: Key) -> Chord {
+        Chord {
+            modifiers: modifiers.iter().copied().collect(),
+            key,
+        }
+    }
+}
+
+/// Alternative names of the keys, on top of the evdev names without the `KEY_` prefix.
+const KEY_ALIASES: [(&str, Key); 5] = [
+    ("return", Key::KEY_ENTER),
+    ("kp_enter", Key::KEY_KPENTER),
+    ("escape", Key::KEY_ESC),
+    ("del", Key::KEY_DELETE),
+    ("ins", Key::KEY_INSERT),
+];
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let (modifiers, key) = s.rsplit_once('+').unwrap_or(("", &s));
+        let modifiers = modifiers
+            .split('+')
+            .filter(|name| !name.is_empty())
+            .map(|name| match name {
+                "ctrl" | "control" => Ok(Modifier::Control),
+                "shift" => Ok(Modifier::Shift),
+                "alt" => Ok(Modifier::Alt),
+                "super" | "meta" | "logo" => Ok(Modifier::Super),
+                "altgr" => Ok(Modifier::AltGr),
+                _ => Err(format!("Unknown modifier {:?} in {:?}", name, s)),
+            })
+            .collect::<Result<_, _>>()?;
+        let key = KEY_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == key)
+            .map(|(_, key)| *key)
+            .or_else(|| Key::from_str(&format!("KEY_{}", key.to_uppercase())).ok())
+            .ok_or_else(|| format!("Unknown key {:?} in {:?}", key, s))?;
+        Ok(Chord { modifiers, key })
+    }
+}
+
+impl TryFrom<String> for Chord {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The table of the [`Action`] each [`Chord`] is bound to.
+pub struct Bindings {
+    bindings: HashMap<Chord, Action>,
+    shortcuts: ShortcutPolicy,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let bindings = [
+            (Key::KEY_ENTER, Action::Submit),
+            (Key::KEY_KPENTER, Action::Submit),
+            (Key::KEY_ESC, Action::Cancel),
+            (Key::KEY_BACKSPACE, Action::Backspace),
+            (Key::KEY_DELETE, Action::Delete),
+            (Key::KEY_LEFT, Action::Left),
+            (Key::KEY_RIGHT, Action::Right),
+            (Key::KEY_UP, Action::Up),
+            (Key::KEY_DOWN, Action::Down),
+            (Key::KEY_HOME, Action::Home),
+            (Key::KEY_END, Action::End),
+            (Key::KEY_TAB, Action::Complete),
+        ]
+        .into_iter()
+        .map(|(key, action)| (Chord::new(&[], key), action))
+        .chain(
+            [
+                (Key::KEY_C, Action::Cancel),
+                (Key::KEY_LEFT, Action::WordLeft),
+                (Key::KEY_RIGHT, Action::WordRight),
+                (Key::KEY_BACKSPACE, Action::WordBackspace),
+                (Key::KEY_DELETE, Action::WordDelete),
+                // readline's emacs bindings
+                (Key::KEY_A, Action::Home),
+                (Key::KEY_E, Action::End),
+                (Key::KEY_B, Action::Left),
+                (Key::KEY_F, Action::Right),
+                (Key::KEY_H, Action::Backspace),
+                (Key::KEY_D, Action::Delete),
+                (Key::KEY_K, Action::KillLine),
+                (Key::KEY_U, Action::UnixLineDiscard),
+                (Key::KEY_W, Action::UnixWordRubout),
+                (Key::KEY_Y, Action::Yank),
+                (Key::KEY_T, Action::TransposeChars),
+                (Key::KEY_Z, Action::Undo),
+                (Key::KEY_V, Action::Paste),
+                (Key::KEY_R, Action::ReverseSearch),
+            ]
+            .into_iter()
+            .map(|(key, action)| (Chord::new(&[Modifier::Control], key), action)),
+        )
+        .chain([
+            (
+                Chord::new(&[Modifier::Control, Modifier::Shift], Key::KEY_Z),
+                Action::Redo,
+            ),
+            (
+                Chord::new(&[Modifier::Shift], Key::KEY_ENTER),
+                Action::Newline,
+            ),
+            (
+                Chord::new(&[Modifier::Shift], Key::KEY_KPENTER),
+                Action::Newline,
+            ),
+            (
+                Chord::new(&[Modifier::Shift], Key::KEY_INSERT),
+                Action::Paste,
+            ),
+            (
+                Chord::new(&[Modifier::Control, Modifier::Shift], Key::KEY_LEFT),
+                Action::SelectWordLeft,
+            ),
+            (
+                Chord::new(&[Modifier::Control, Modifier::Shift], Key::KEY_RIGHT),
+                Action::SelectWordRight,
+            ),
+        ])
+        .chain(
+            [
+                (Key::KEY_LEFT, Action::SelectLeft),
+                (Key::KEY_RIGHT, Action::SelectRight),
+                (Key::KEY_HOME, Action::SelectHome),
+                (Key::KEY_END, Action::SelectEnd),
+            ]
+            .into_iter()
+            .map(|(key, action)| (Chord::new(&[Modifier::Shift], key), action)),
+        )
+        .chain(
+            [
+                (Key::KEY_B, Action::WordLeft),
+                (Key::KEY_F, Action::WordRight),
+                (Key::KEY_D, Action::KillWord),
+                (Key::KEY_BACKSPACE, Action::BackwardKillWord),
+                (Key::KEY_Y, Action::YankPop),
+            ]
+            .into_iter()
+            .map(|(key, action)| (Chord::new(&[Modifier::Alt], key), action)),
+        )
+        .collect();
+        Bindings {
+            bindings,
+            shortcuts: ShortcutPolicy::default(),
+        }
+    }
+}
+
+impl Bindings {
+    /// Create the default [`Bindings`] of the editing `mode`.
+    ///
+    /// In the vi editing mode, Esc switches to the normal mode instead of cancelling.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The [`EditingMode`] of the entry.
+    pub fn new(mode: EditingMode) -> Bindings {
+        let mut bindings = Bindings::default();
+        if mode == EditingMode::Vi {
+            bindings
+                .bindings
+                .insert(Chord::new(&[], Key::KEY_ESC), Action::NormalMode);
+        }
+        bindings
+    }
+
+    /// Bind the chords of `bindings`, replacing their current bindings.
+    ///
+    /// # Arguments
+    ///
+    /// * `bindings` - The [`Action`] to bind each [`Chord`] to.
+    pub fn extend(&mut self, bindings: &HashMap<Chord, Action>) {
+        self.bindings.extend(
+            bindings
+                .iter()
+                .map(|(chord, action)| (chord.clone(), *action)),
+        );
+    }
+
+    /// Set what to do with the unbound shortcuts.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The [`ShortcutPolicy`] of the unbound shortcuts.
+    pub fn set_shortcuts(&mut self, policy: ShortcutPolicy) {
+        self.shortcuts = policy;
+    }
+
+    /// Get the [`Action`] bound to the `key` pressed with the `modifiers`.
+    ///
+    /// The chord with exactly the held modifiers is looked up first, then the one without the
+    /// level modifiers, so that e.g. Shift+Backspace still deletes a character. The unbound
+    /// shortcuts, i.e. chords with Ctrl, Alt or Super, get the [`ShortcutPolicy`]'s action.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The pressed [`Key`].
+    /// * `modifiers` - The held modifiers.
+    pub fn action<'a>(
+        &self,
+        key: Key,
+        modifiers: impl IntoIterator<Item = &'a Modifier>,
+    ) -> Option<Action> {
+        let mut chord = Chord {
+            modifiers: modifiers.into_iter().copied().collect(),
+            key,
+        };
+        let action = self.bindings.get(&chord).copied().or_else(|| {
+            chord.modifiers.retain(|modifier| !modifier.is_level());
+            self.bindings.get(&chord).copied()
+        });
+        action.filter(|action| *action != Action::None).or_else(|| {
+            let is_shortcut = chord.modifiers.iter().any(|modifier| !modifier.is_level());
+            is_shortcut.then_some(match self.shortcuts {
+                ShortcutPolicy::Pass => Action::Pass,
+                ShortcutPolicy::Block => Action::Block,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_chord() {
+        assert_eq!("enter".parse(), Ok(Chord::new(&[], Key::KEY_ENTER)));
+        assert_eq!(
+            "Ctrl+Shift+Return".parse(),
+            Ok(Chord::new(
+                &[Modifier::Control, Modifier::Shift],
+                Key::KEY_ENTER
+            ))
+        );
+        assert_eq!(
+            "super+kp_enter".parse(),
+            Ok(Chord::new(&[Modifier::Super], Key::KEY_KPENTER))
+        );
+        assert!("hyper+a".parse::<Chord>().is_err());
+        assert!("ctrl+foo".parse::<Chord>().is_err());
+    }
+
+    #[test]
+    fn test_bindings() {
+        let mut bindings = Bindings::default();
+        assert_eq!(bindings.action(Key::KEY_ENTER, &[]), Some(Action::Submit));
+        assert_eq!(
+            bindings.action(Key::KEY_ENTER, &[Modifier::Shift]),
+            Some(Action::Newline)
+        );
+        assert_eq!(
+            bindings.action(Key::KEY_C, &[Modifier::Control]),
+            Some(Action::Cancel)
+        );
+        assert_eq!(bindings.action(Key::KEY_C, &[]), None);
+        assert_eq!(
+            bindings.action(Key::KEY_Y, &[Modifier::Alt]),
+            Some(Action::YankPop)
+        );
+        assert_eq!(bindings.action(Key::KEY_C, &[Modifier::Shift]), None);
+        assert_eq!(
+            bindings.action(Key::KEY_INSERT, &[Modifier::Shift]),
+            Some(Action::Paste)
+        );
+        assert_eq!(bindings.action(Key::KEY_INSERT, &[]), None);
+        assert_eq!(
+            bindings.action(Key::KEY_HOME, &[Modifier::Shift]),
+            Some(Action::SelectHome)
+        );
+        assert_eq!(
+            bindings.action(Key::KEY_LEFT, &[Modifier::Control, Modifier::Shift]),
+            Some(Action::SelectWordLeft)
+        );
+        assert_eq!(
+            bindings.action(Key::KEY_RIGHT, &[Modifier::Shift, Modifier::Control]),
+            Some(Action::SelectWordRight)
+        );
+        assert_eq!(
+            bindings.action(Key::KEY_Z, &[Modifier::Control]),
+            Some(Action::Undo)
+        );
+        assert_eq!(
+            bindings.action(Key::KEY_Z, &[Modifier::Shift, Modifier::Control]),
+            Some(Action::Redo)
+        );
+        assert_eq!(
+            bindings.action(Key::KEY_BACKSPACE, &[Modifier::Control]),
+            Some(Action::WordBackspace)
+        );
+        // the level modifiers are ignored if the chord isn't bound with them
+        assert_eq!(
+            bindings.action(Key::KEY_BACKSPACE, &[Modifier::Shift]),
+            Some(Action::Backspace)
+        );
+        // the unbound shortcuts are passed through
+        assert_eq!(
+            bindings.action(Key::KEY_INSERT, &[Modifier::Alt]),
+            Some(Action::Pass)
+        );
+        assert_eq!(
+            bindings.action(Key::KEY_TAB, &[Modifier::Alt, Modifier::Shift]),
+            Some(Action::Pass)
+        );
+
+        bindings.extend(&HashMap::from([
+            ("ctrl+enter".parse().unwrap(), Action::Submit),
+            ("shift+enter".parse().unwrap(), Action::None),
+            ("enter".parse().unwrap(), Action::None),
+            ("esc".parse().unwrap(), Action::Clear),
+        ]));
+        assert_eq!(bindings.action(Key::KEY_ENTER, &[]), None);
+        assert_eq!(bindings.action(Key::KEY_ENTER, &[Modifier::Shift]), None);
+        assert_eq!(
+            bindings.action(Key::KEY_ENTER, &[Modifier::Control]),
+            Some(Action::Submit)
+        );
+        assert_eq!(bindings.action(Key::KEY_ESC, &[]), Some(Action::Clear));
+
+        bindings.set_shortcuts(ShortcutPolicy::Block);
+        assert_eq!(
+            bindings.action(Key::KEY_A, &[Modifier::Super]),
+            Some(Action::Block)
+        );
+        bindings.extend(&HashMap::from([
+            ("super+a".parse().unwrap(), Action::Pass),
+            ("ctrl+c".parse().unwrap(), Action::None),
+        ]));
+        assert_eq!(
+            bindings.action(Key::KEY_A, &[Modifier::Super]),
+            Some(Action::Pass)
+        );
+        assert_eq!(
+            bindings.action(Key::KEY_C, &[Modifier::Control]),
+            Some(Action::Block)
+        );
+
+        let bindings = Bindings::new(EditingMode::Vi);
+        assert_eq!(bindings.action(Key::KEY_ESC, &[]), Some(Action::NormalMode));
+        assert_eq!(
+            bindings.action(Key::KEY_C, &[Modifier::Control]),
+            Some(Action::Cancel)
+        );
+        assert_eq!(Bindings::new(EditingMode::Emacs), Bindings::default());
+    }
+}
@@ -1,6 +1,8 @@
 use clap::Parser;
 use clap_verbosity_flag::{LogLevel, Verbosity};
 
+use crate::terminal::UnicodeFallback;
+
 fn validate_shell_cmd(arg: &str) -> Result<String, String> {
     if shlex::split(arg).is_some() {
         Ok(arg.to_string())
@@ -63,10 +65,26 @@ pub struct Arguments {
     #[clap(short = 'T', long)]
     pub type_output: bool,
 
+    /// How to type the characters which aren't in the keyboard layout, with -T
+    #[clap(short = 'u', long, value_enum, default_value_t = UnicodeFallback::Paste)]
+    pub unicode_fallback: UnicodeFallback,
+
     /// Add delay between keypresses, in ms, values between 1 and 10 work best
     #[clap(short = 'd', long, value_parser=duration_parser, default_value="5")]
     pub key_delay: Option<std::time::Duration>,
 
+    /// Edit the entry in the vi mode, Esc switches to the normal mode
+    #[clap(long)]
+    pub vi: bool,
+
+    /// Use the settings of this profile of the config file
+    #[clap(short = 'p', long)]
+    pub profile: Option<String>,
+
+    /// Path of the config file [default: $XDG_CONFIG_HOME/shiv/config.toml]
+    #[clap(short = 'c', long)]
+    pub config: Option<std::path::PathBuf>,
+
     #[command(flatten)]
     pub verbose: Verbosity<Level>,
 }
@@ -80,5 +98,14 @@ mod test {
         let args = Arguments::parse_from(["shiv", "-d", "100", "bash -c"]);
         assert_eq!(args.key_delay, Some(std::time::Duration::from_millis(100)));
         assert_eq!(args.pre_cmd, "bash -c");
+        assert_eq!(args.unicode_fallback, UnicodeFallback::Paste);
+        assert!(!args.vi);
+
+        let args = Arguments::parse_from(["shiv", "-T", "-u", "ctrl-shift-u"]);
+        assert_eq!(args.unicode_fallback, UnicodeFallback::CtrlShiftU);
+
+        let args = Arguments::parse_from(["shiv", "--vi", "-p", "vim"]);
+        assert!(args.vi);
+        assert_eq!(args.profile.as_deref(), Some("vim"));
     }
 }
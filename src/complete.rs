@@ -0,0 +1,414 @@
+use serde::Deserialize;
+
+use std::{
+    error::Error,
+    ffi::OsString,
+    fmt::Debug,
+    io::Read,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+use crate::permissions;
+
+/// Time a completion command gets to answer, its candidates are listed in the background.
+const COMPLETION_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Where the completion candidates come from.
+pub enum CompleterConfig {
+    /// The paths and the executables of the `PATH`.
+    #[default]
+    Builtin,
+    /// Bash's `compgen`, run as the user.
+    Bash,
+    /// A command run as the user with the entry before the cursor as last argument, which prints
+    /// a candidate per line.
+    Command(String),
+}
+
+/// Provides the candidates to complete the word before the cursor with.
+pub trait Completer: Debug + Send + Sync {
+    /// Get the candidates to replace the word before the cursor with.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The entry up to the cursor, the word to complete is its end.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the candidates can't be listed.
+    fn complete(&self, line: &str) -> Result<Vec<String>, Box<dyn Error>>;
+}
+
+/// Create the [`Completer`] of the `config`.
+///
+/// # Arguments
+///
+/// * `config` - The [`CompleterConfig`] to use.
+/// * `uid` - The UID of the user to run the completion commands as.
+/// * `home` - The home directory of the user, which the relative paths start from.
+///
+/// # Errors
+///
+/// This function will return an error if the completion command can't be parsed.
+pub fn completer(
+    config: &CompleterConfig,
+    uid: u32,
+    home: Option<PathBuf>,
+) -> Result<Arc<dyn Completer>, Box<dyn Error>> {
+    Ok(match config {
+        CompleterConfig::Builtin => Arc::new(Builtin {
+            home,
+            path: permissions::command_path().unwrap_or_default().into(),
+        }),
+        CompleterConfig::Bash => Arc::new(Bash { uid }),
+        CompleterConfig::Command(command) => Arc::new(External {
+            command: shlex::split(command)
+                .filter(|command| !command.is_empty())
+                .ok_or(format!("Failed to parse completion command {}", command))?,
+            uid,
+        }),
+    })
+}
+
+/// Split the `line` before the blank delimited word at its end, and that word.
+pub fn split_word(line: &str) -> (&str, &str) {
+    let start = line
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    line.split_at(start)
+}
+
+/// Run a command and get its output, killing it if it doesn't exit within the `timeout`.
+///
+/// The output is read within the same `timeout`, as a process left in the background by the
+/// command can keep it open.
+///
+/// # Errors
+///
+/// This function will return an error if the command fails or times out.
+fn run_with_timeout(mut command: Command, timeout: Duration) -> Result<String, Box<dyn Error>> {
+    log::debug!("Running completion command: {:?}", command);
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // read in the background, so that a large output doesn't block the command
+    let mut stdout = child.stdout.take().ok_or("No stdout")?;
+    let (send, recv) = mpsc::channel();
+    // the reader is detached, it ends once the output is closed
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = send.send(stdout.read_to_string(&mut output).map(|_| output));
+    });
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(format!("Completion command timed out: {:?}", command).into());
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    let output = recv
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .map_err(|_| format!("Completion command output timed out: {:?}", command))??;
+    if !status.success() {
+        return Err(format!("Completion command failed: {}", status).into());
+    }
+    Ok(output)
+}
+
+/// Create the command to run `args` as the user, in their environment like the entry.
+///
+/// # Errors
+///
+/// This function will return an error if the command can't be set up to run as the user.
+fn user_command(uid: u32, args: &[String]) -> Result<Command, Box<dyn Error>> {
+    let (program, args) = args.split_first().ok_or("Empty command")?;
+    let mut command = Command::new(program);
+    command.args(args);
+    permissions::run_as_user(&mut command, uid)?;
+    Ok(command)
+}
+
+/// Parse the candidates printed a line each.
+fn parse_candidates(output: &str) -> Vec<String> {
+    let mut candidates: Vec<String> = output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/// Quote a candidate of [`Bash`] to insert it in the command line, e.g. `'my notes.txt'`.
+///
+/// The leading `~/` and the trailing `/` of the directories are left out of the quotes, for the
+/// tilde to expand and the terminal to keep completing in the directory.
+fn quote_candidate(candidate: &str) -> Option<String> {
+    let (tilde, rest) = match candidate.strip_prefix("~/") {
+        Some(rest) => ("~/", rest),
+        None => ("", candidate),
+    };
+    let (name, slash) = match rest.strip_suffix('/') {
+        Some(name) if !name.is_empty() => (name, "/"),
+        _ => (rest, ""),
+    };
+    if name.is_empty() {
+        return Some(candidate.to_string());
+    }
+    let name = shlex::try_quote(name).ok()?;
+    Some(format!("{}{}{}", tilde, name, slash))
+}
+
+/// Whether the word at the end of the `line` is the command name.
+fn is_command(line: &str) -> bool {
+    let (before, word) = split_word(line);
+    before.trim().is_empty() && !word.contains('/')
+}
+
+#[derive(Debug)]
+/// Completes the first word with the executables of the `PATH`, and the others with paths.
+pub struct Builtin {
+    home: Option<PathBuf>,
+    /// The `PATH` the commands run with.
+    path: OsString,
+}
+
+impl Builtin {
+    fn executables(&self, prefix: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = std::env::split_paths(&self.path)
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flatten()
+            .flatten()
+            .filter(|entry| {
+                // follow the symbolic links, unlike `DirEntry::metadata`
+                std::fs::metadata(entry.path()).is_ok_and(|metadata| {
+                    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+                })
+            })
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
+    fn paths(&self, word: &str) -> Vec<String> {
+        let (dir, prefix) = word.split_at(word.rfind('/').map_or(0, |i| i + 1));
+        let home = self.home.clone().unwrap_or_else(|| PathBuf::from("/"));
+        // the commands run in the home directory
+        let path = match dir.strip_prefix("~/") {
+            Some(rest) => home.join(rest),
+            None if dir.is_empty() => home,
+            None => home.join(dir),
+        };
+        let Ok(entries) = std::fs::read_dir(&path) else {
+            return Vec::new();
+        };
+        let mut candidates: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.'))
+                {
+                    return None;
+                }
+                let is_dir = entry.path().is_dir();
+                Some(format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }))
+            })
+            .collect();
+        candidates.sort();
+        candidates
+    }
+}
+
+impl Completer for Builtin {
+    fn complete(&self, line: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let (_, word) = split_word(line);
+        if is_command(line) && !word.is_empty() {
+            Ok(self.executables(word))
+        } else {
+            Ok(self.paths(word))
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Completes with bash's `compgen`, run as the user.
+pub struct Bash {
+    uid: u32,
+}
+
+impl Completer for Bash {
+    fn complete(&self, line: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let (_, word) = split_word(line);
+        let script = if is_command(line) {
+            "compgen -c -- \"$1\""
+        } else {
+            // mark the directories with a `/`, like the builtin completer
+            r#"compgen -f -- "$1" | while IFS= read -r path; do
+                if [[ -d ${path/#\~/$HOME} ]]; then printf '%s/\n' "$path"; else printf '%s\n' "$path"; fi
+            done"#
+        };
+        let args = [
+            "bash".to_string(),
+            "-c".to_string(),
+            script.to_string(),
+            "shiv".to_string(),
+            word.to_string(),
+        ];
+        let output = run_with_timeout(user_command(self.uid, &args)?, COMPLETION_TIMEOUT)?;
+        // the names are inserted in the command line, e.g. `my notes.txt` or `a&b`
+        Ok(parse_candidates(&output)
+            .iter()
+            .filter_map(|candidate| quote_candidate(candidate))
+            .collect())
+    }
+}
+
+#[derive(Debug)]
+/// Completes with an external command, e.g. provided by the pre command.
+pub struct External {
+    command: Vec<String>,
+    uid: u32,
+}
+
+impl Completer for External {
+    fn complete(&self, line: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let args = [self.command.as_slice(), &[line.to_string()]].concat();
+        let output = run_with_timeout(user_command(self.uid, &args)?, COMPLETION_TIMEOUT)?;
+        Ok(parse_candidates(&output))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_word() {
+        assert_eq!(split_word("ls -la ~/fo"), ("ls -la ", "~/fo"));
+        assert_eq!(split_word("ls "), ("ls ", ""));
+        assert_eq!(split_word("ls"), ("", "ls"));
+        assert_eq!(split_word("echo\u{a0}é"), ("echo\u{a0}", "é"));
+        assert!(is_command("  gi"));
+        assert!(!is_command("./gi"));
+        assert!(!is_command("git st"));
+    }
+
+    #[test]
+    fn test_builtin_completer() {
+        let home = std::env::temp_dir().join(format!("shiv-test-complete-{}", std::process::id()));
+        std::fs::create_dir_all(home.join("docs")).unwrap();
+        for file in ["notes.txt", "notebook", ".hidden", "docs/todo"] {
+            std::fs::write(home.join(file), "").unwrap();
+        }
+        let bin = home.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        for (file, mode) in [("shiv-fake", 0o755), ("shiv-data", 0o644)] {
+            std::fs::write(bin.join(file), "").unwrap();
+            std::fs::set_permissions(bin.join(file), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+        }
+        std::fs::create_dir_all(bin.join("shiv-dir")).unwrap();
+        let completer = Builtin {
+            home: Some(home.clone()),
+            path: std::env::join_paths([bin, home.join("missing")]).unwrap(),
+        };
+        assert_eq!(
+            completer.complete("cat no").unwrap(),
+            ["notebook", "notes.txt"]
+        );
+        assert_eq!(completer.complete("cat d").unwrap(), ["docs/"]);
+        assert_eq!(completer.complete("cat ~/docs/").unwrap(), ["~/docs/todo"]);
+        assert_eq!(completer.complete("cat .h").unwrap(), [".hidden"]);
+        assert_eq!(completer.complete("cat ").unwrap().len(), 4);
+        assert!(completer.complete("cat nope/").unwrap().is_empty());
+
+        // only the executable files of the `PATH` are commands
+        assert_eq!(completer.complete("shiv").unwrap(), ["shiv-fake"]);
+        assert!(completer.complete("ls").unwrap().is_empty());
+        std::fs::remove_dir_all(home).unwrap();
+    }
+
+    #[test]
+    fn test_bash_completer() {
+        let dir = std::env::temp_dir().join(format!("shiv-test-bash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["my notes.txt", "a&b", "plain"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        std::fs::create_dir_all(dir.join("sub dir")).unwrap();
+        let completer = Bash {
+            uid: nix::unistd::getuid().as_raw(),
+        };
+        let dir = dir.to_str().unwrap();
+        assert_eq!(
+            completer.complete(&format!("cat {}/", dir)).unwrap(),
+            [
+                format!("'{}/a&b'", dir),
+                format!("'{}/my notes.txt'", dir),
+                format!("{}/plain", dir),
+                format!("'{}/sub dir'/", dir),
+            ]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_quote_candidate() {
+        assert_eq!(quote_candidate("plain").unwrap(), "plain");
+        assert_eq!(quote_candidate("a&b").unwrap(), "'a&b'");
+        assert_eq!(quote_candidate("my docs/").unwrap(), "'my docs'/");
+        assert_eq!(quote_candidate("~/my docs/").unwrap(), "~/'my docs'/");
+        assert_eq!(quote_candidate("~/").unwrap(), "~/");
+        assert_eq!(quote_candidate("/").unwrap(), "/");
+    }
+
+    #[test]
+    fn test_run_with_timeout() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "printf 'b\\na\\n\\nb\\n'"]);
+        let output = run_with_timeout(command, COMPLETION_TIMEOUT).unwrap();
+        assert_eq!(parse_candidates(&output), ["a", "b"]);
+
+        let mut command = Command::new("sleep");
+        command.arg("5");
+        assert!(run_with_timeout(command, Duration::from_millis(50)).is_err());
+        assert!(run_with_timeout(Command::new("false"), COMPLETION_TIMEOUT).is_err());
+
+        // a background process holding the output doesn't block the read
+        let mut command = Command::new("sh");
+        command.args(["-c", "sleep 5 & echo x"]);
+        let start = Instant::now();
+        assert!(run_with_timeout(command, Duration::from_millis(200)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_completer_config() {
+        assert!(completer(&CompleterConfig::Command("'".to_string()), 0, None).is_err());
+        assert!(completer(
+            &CompleterConfig::Command("qalc-complete".to_string()),
+            0,
+            None
+        )
+        .is_ok());
+    }
+}
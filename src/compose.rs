@@ -0,0 +1,478 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::CharIndices,
+};
+
+use crate::xkb;
+
+/// Directory of the system's locale specific Compose tables.
+const LOCALE_DIR: &str = "/usr/share/X11/locale";
+
+/// Maximum depth of nested `include` statements.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Debug)]
+enum Node {
+    /// The text a complete sequence produces.
+    Leaf(String),
+    /// The sequence continues with one of these keysyms.
+    Branch(HashMap<u32, Node>),
+}
+
+#[derive(Debug)]
+/// A table of dead key and Compose sequences, as found in Compose files.
+pub struct Table {
+    root: Node,
+}
+
+impl Table {
+    /// Load the user's Compose table.
+    ///
+    /// Like libX11, the first of `$XCOMPOSEFILE`, `$XDG_CONFIG_HOME/XCompose`, `~/.XCompose` and
+    /// the locale's system Compose file to exist is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `home` - The user's home directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the Compose file fails to be read.
+    pub fn load(home: Option<&Path>) -> Result<Option<Table>, Box<dyn Error>> {
+        let env_path = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+        let candidates = [
+            env_path("XCOMPOSEFILE").map(PathBuf::from),
+            env_path("XDG_CONFIG_HOME").map(|dir| PathBuf::from(dir).join("XCompose")),
+            home.map(|home| home.join(".XCompose")),
+            locale_compose_file(&locale()),
+        ];
+        let Some(path) = candidates.into_iter().flatten().find(|path| path.is_file()) else {
+            return Ok(None);
+        };
+        log::debug!("Loading Compose file: {:?}", path);
+        Table::from_file(&path, home).map(Some)
+    }
+
+    /// Parse a Compose file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the Compose file.
+    /// * `home` - The user's home directory, to resolve `%H` in includes.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file fails to be read.
+    pub fn from_file(path: &Path, home: Option<&Path>) -> Result<Table, Box<dyn Error>> {
+        let mut table = Table::new();
+        table.add_file(path, home, 0)?;
+        Ok(table)
+    }
+
+    fn new() -> Table {
+        Table {
+            root: Node::Branch(HashMap::new()),
+        }
+    }
+
+    fn add_file(
+        &mut self,
+        path: &Path,
+        home: Option<&Path>,
+        depth: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read Compose file {:?}: {}", path, e))?;
+        self.add_contents(&contents, home, depth)
+    }
+
+    fn add_contents(
+        &mut self,
+        contents: &str,
+        home: Option<&Path>,
+        depth: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        for line in contents.lines() {
+            match parse_line(line) {
+                Some(Line::Include(include)) => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        log::warn!("Compose include depth exceeded, ignoring: {:?}", include);
+                        continue;
+                    }
+                    let Some(path) = expand_include(&include, home) else {
+                        log::warn!("Failed to resolve Compose include: {:?}", include);
+                        continue;
+                    };
+                    self.add_file(&path, home, depth + 1)?;
+                }
+                Some(Line::Sequence(sequence, text)) => self.insert(&sequence, text),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a sequence, overriding the sequences it conflicts with.
+    fn insert(&mut self, sequence: &[u32], text: String) {
+        let mut node = &mut self.root;
+        for keysym in sequence {
+            if let Node::Leaf(_) = node {
+                *node = Node::Branch(HashMap::new());
+            }
+            let Node::Branch(children) = node else {
+                unreachable!()
+            };
+            node = children
+                .entry(*keysym)
+                .or_insert_with(|| Node::Branch(HashMap::new()));
+        }
+        *node = Node::Leaf(text);
+    }
+
+    fn get(&self, sequence: &[u32]) -> Option<&Node> {
+        sequence
+            .iter()
+            .try_fold(&self.root, |node, keysym| match node {
+                Node::Branch(children) => children.get(keysym),
+                Node::Leaf(_) => None,
+            })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Line {
+    Include(String),
+    Sequence(Vec<u32>, String),
+}
+
+/// Parse a line of a Compose file.
+///
+/// Returns `None` for comments, blank lines and lines which fail to parse.
+fn parse_line(line: &str) -> Option<Line> {
+    let line = line.trim();
+    if let Some(include) = line.strip_prefix("include") {
+        let (path, _) = parse_string(include.trim_start())?;
+        return Some(Line::Include(path));
+    }
+    let (lhs, rhs) = line.split_once(':')?;
+    if lhs.trim_start().starts_with('#') {
+        return None;
+    }
+    let mut sequence = Vec::new();
+    let mut lhs = lhs;
+    while let Some(start) = lhs.find('<') {
+        let end = start + lhs[start..].find('>')?;
+        sequence.push(xkb::keysym_from_name(&lhs[start + 1..end])?);
+        lhs = &lhs[end + 1..];
+    }
+    if sequence.is_empty() {
+        return None;
+    }
+
+    let rhs = rhs.trim_start();
+    let text = if rhs.starts_with('"') {
+        parse_string(rhs)?.0
+    } else {
+        // only a keysym is given
+        let name = rhs.split(|c: char| c.is_whitespace() || c == '#').next()?;
+        xkb::keysym_to_char(xkb::keysym_from_name(name)?)?.to_string()
+    };
+    Some(Line::Sequence(sequence, text))
+}
+
+/// Parse a double quoted string with C style escapes.
+///
+/// The octal and hexadecimal escapes are bytes of the UTF-8 encoded string.
+///
+/// Returns the unescaped string and the remainder of the input.
+fn parse_string(input: &str) -> Option<(String, &str)> {
+    let mut chars = input.strip_prefix('"')?.char_indices().peekable();
+    let mut out = Vec::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((String::from_utf8(out).ok()?, &input[i + 2..])),
+            '\\' => {
+                let (_, escaped) = chars.next()?;
+                match escaped {
+                    'n' => out.push(b'\n'),
+                    'r' => out.push(b'\r'),
+                    't' => out.push(b'\t'),
+                    'x' | 'X' => out.push(take_digits(&mut chars, 16, 0, 2)?),
+                    '0'..='7' => out.push(take_digits(&mut chars, 8, escaped.to_digit(8)?, 2)?),
+                    c => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                }
+            }
+            c => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    None
+}
+
+/// Parse the digits of an octal or hexadecimal escape into a byte.
+///
+/// # Arguments
+///
+/// * `chars` - The characters following the escape.
+/// * `radix` - The base of the digits.
+/// * `first` - The value of the digits already parsed.
+/// * `max` - The maximum number of digits to parse.
+fn take_digits(
+    chars: &mut Peekable<CharIndices>,
+    radix: u32,
+    first: u32,
+    max: usize,
+) -> Option<u8> {
+    let mut code = first;
+    for _ in 0..max {
+        let Some(digit) = chars.peek().and_then(|(_, c)| c.to_digit(radix)) else {
+            break;
+        };
+        code = code * radix + digit;
+        chars.next();
+    }
+    u8::try_from(code).ok()
+}
+
+/// Expand the `%H`, `%L` and `%S` substitutions of an include path.
+fn expand_include(include: &str, home: Option<&Path>) -> Option<PathBuf> {
+    let mut out = String::new();
+    let mut chars = include.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'H' => out.push_str(home?.to_str()?),
+            'L' => out.push_str(locale_compose_file(&locale())?.to_str()?),
+            'S' => out.push_str(LOCALE_DIR),
+            '%' => out.push('%'),
+            _ => return None,
+        }
+    }
+    Some(PathBuf::from(out))
+}
+
+/// Get the locale used for character handling.
+fn locale() -> String {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_else(|| "C".to_string())
+}
+
+/// Find the system Compose file of a locale.
+fn locale_compose_file(locale: &str) -> Option<PathBuf> {
+    let compose_dir = std::fs::read_to_string(Path::new(LOCALE_DIR).join("compose.dir")).ok()?;
+    // locales are commonly spelled e.g. en_US.utf8 while compose.dir uses en_US.UTF-8
+    let normalized = match locale.split_once('.') {
+        Some((name, codeset)) if codeset.eq_ignore_ascii_case("utf8") => {
+            format!("{}.UTF-8", name)
+        }
+        _ => locale.to_string(),
+    };
+    compose_dir
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(|c: char| c.is_whitespace()))
+        .find(|(_, name)| name.trim() == normalized)
+        .map(|(file, _)| Path::new(LOCALE_DIR).join(file.trim_end_matches(':')))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// The result of feeding a keysym to a [`ComposeState`].
+pub enum Status {
+    /// The keysym is not part of a sequence.
+    Nothing,
+    /// The keysym started or continued a sequence.
+    Composing,
+    /// The keysym completed a sequence, producing this text.
+    Composed(String),
+    /// The keysym doesn't continue the current sequence, which is dropped.
+    Cancelled,
+}
+
+#[derive(Debug)]
+/// Follow the dead key and Compose sequences as they are typed.
+pub struct ComposeState {
+    table: Table,
+    sequence: Vec<u32>,
+}
+
+impl ComposeState {
+    /// Create a new [`ComposeState`].
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The [`Table`] of sequences.
+    pub fn new(table: Table) -> ComposeState {
+        ComposeState {
+            table,
+            sequence: Vec::new(),
+        }
+    }
+
+    /// Feed the keysym of a key press.
+    ///
+    /// # Arguments
+    ///
+    /// * `keysym` - The keysym of the pressed key.
+    pub fn feed(&mut self, keysym: u32) -> Status {
+        if is_modifier(keysym) {
+            // modifiers don't interrupt sequences
+            return if self.sequence.is_empty() {
+                Status::Nothing
+            } else {
+                Status::Composing
+            };
+        }
+        self.sequence.push(keysym);
+        match self.table.get(&self.sequence) {
+            Some(Node::Branch(_)) => Status::Composing,
+            Some(Node::Leaf(text)) => {
+                let text = text.clone();
+                self.sequence.clear();
+                Status::Composed(text)
+            }
+            None => {
+                let started = self.sequence.len() > 1;
+                self.sequence.clear();
+                if started {
+                    Status::Cancelled
+                } else {
+                    Status::Nothing
+                }
+            }
+        }
+    }
+}
+
+/// Whether the keysym is a modifier, e.g. Shift_L or ISO_Level3_Shift.
+fn is_modifier(keysym: u32) -> bool {
+    // Shift_L..Hyper_R, ISO_Lock..ISO_Level5_Lock, Mode_switch and Num_Lock
+    (0xffe1..=0xffee).contains(&keysym)
+        || (0xfe01..=0xfe13).contains(&keysym)
+        || keysym == 0xff7e
+        || keysym == 0xff7f
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keysym(name: &str) -> u32 {
+        xkb::keysym_from_name(name).unwrap()
+    }
+
+    fn test_table() -> Table {
+        let mut table = Table::new();
+        table
+            .add_contents(
+                "# comment: with a colon\n\
+                 <dead_acute> <e> : \"é\" eacute # LATIN SMALL LETTER E WITH ACUTE\n\
+                 <dead_acute> <space> : \"'\" apostrophe\n\
+                 <Multi_key> <o> <c> : \"©\" copyright\n\
+                 <Multi_key> <slash> <slash> : \"\\\\\" backslash\n\
+                 <Multi_key> <minus> <minus> <period> : \"\\342\\200\\223\"\n\
+                 <Multi_key> <e> <e> : schwa\n",
+                None,
+                0,
+            )
+            .unwrap();
+        table
+    }
+
+    #[test]
+    fn test_parse_string() {
+        assert_eq!(
+            parse_string("\"a\\\"b\" rest"),
+            Some(("a\"b".to_string(), " rest"))
+        );
+        assert_eq!(parse_string("\"\\x41\\102\""), Some(("AB".to_string(), "")));
+        assert_eq!(parse_string("\"unterminated"), None);
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line("include \"%L\""),
+            Some(Line::Include("%L".to_string()))
+        );
+        assert_eq!(
+            parse_line("<dead_grave> <a> : \"à\" agrave"),
+            Some(Line::Sequence(
+                vec![keysym("dead_grave"), keysym("a")],
+                "à".to_string()
+            ))
+        );
+        assert_eq!(parse_line("# <dead_grave> <a> : \"à\""), None);
+        assert_eq!(parse_line("<not_a_keysym> <a> : \"à\""), None);
+        assert_eq!(parse_line(""), None);
+    }
+
+    #[test]
+    fn test_expand_include() {
+        assert_eq!(
+            expand_include("%H/.XCompose.local", Some(Path::new("/home/user"))),
+            Some(PathBuf::from("/home/user/.XCompose.local"))
+        );
+        assert_eq!(
+            expand_include("%S/en_US.UTF-8/Compose", None),
+            Some(PathBuf::from("/usr/share/X11/locale/en_US.UTF-8/Compose"))
+        );
+        assert_eq!(expand_include("%H/.XCompose", None), None);
+    }
+
+    #[test]
+    fn test_compose_state() {
+        let mut state = ComposeState::new(test_table());
+        assert_eq!(state.feed(keysym("a")), Status::Nothing);
+
+        assert_eq!(state.feed(keysym("dead_acute")), Status::Composing);
+        assert_eq!(state.feed(keysym("e")), Status::Composed("é".to_string()));
+
+        assert_eq!(state.feed(keysym("Multi_key")), Status::Composing);
+        assert_eq!(state.feed(keysym("Shift_L")), Status::Composing);
+        assert_eq!(state.feed(keysym("o")), Status::Composing);
+        assert_eq!(state.feed(keysym("c")), Status::Composed("©".to_string()));
+
+        assert_eq!(state.feed(keysym("Multi_key")), Status::Composing);
+        assert_eq!(state.feed(keysym("slash")), Status::Composing);
+        assert_eq!(
+            state.feed(keysym("slash")),
+            Status::Composed("\\".to_string())
+        );
+
+        assert_eq!(state.feed(keysym("Multi_key")), Status::Composing);
+        assert_eq!(state.feed(keysym("minus")), Status::Composing);
+        assert_eq!(state.feed(keysym("minus")), Status::Composing);
+        assert_eq!(
+            state.feed(keysym("period")),
+            Status::Composed("–".to_string())
+        );
+
+        assert_eq!(state.feed(keysym("Multi_key")), Status::Composing);
+        assert_eq!(state.feed(keysym("e")), Status::Composing);
+        assert_eq!(state.feed(keysym("e")), Status::Composed("ə".to_string()));
+
+        assert_eq!(state.feed(keysym("dead_acute")), Status::Composing);
+        assert_eq!(state.feed(keysym("x")), Status::Cancelled);
+        assert_eq!(state.feed(keysym("x")), Status::Nothing);
+    }
+
+    #[test]
+    fn test_table_override() {
+        let mut table = test_table();
+        table.insert(&[keysym("dead_acute"), keysym("e")], "e".to_string());
+        table.insert(&[keysym("Multi_key"), keysym("o")], "o".to_string());
+        let mut state = ComposeState::new(table);
+        assert_eq!(state.feed(keysym("dead_acute")), Status::Composing);
+        assert_eq!(state.feed(keysym("e")), Status::Composed("e".to_string()));
+        assert_eq!(state.feed(keysym("Multi_key")), Status::Composing);
+        assert_eq!(state.feed(keysym("o")), Status::Composed("o".to_string()));
+    }
+}
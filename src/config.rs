@@ -0,0 +1,224 @@
+use serde::Deserialize;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use crate::{bindings, complete, history, repeat, utils, vi, xkb};
+
+/// The profile whose settings apply to all the others.
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Contents of the `shiv` config file.
+pub struct Config {
+    /// The keyboard layout used to decode the user's inputs.
+    pub keyboard: xkb::RuleNames,
+    /// The keyboard layouts of specific devices, by device name, falling back to `keyboard`.
+    pub devices: HashMap<String, xkb::RuleNames>,
+    /// The autorepeat of the held keys.
+    pub repeat: repeat::RepeatConfig,
+    /// The history of the submitted entries.
+    pub history: history::HistoryConfig,
+    /// Named sets of settings, picked with the `--profile` option.
+    pub profiles: HashMap<String, Profile>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// The settings of a profile.
+pub struct Profile {
+    /// The actions bound to the keys, on top of the default bindings.
+    pub bindings: HashMap<bindings::Chord, bindings::Action>,
+    /// What to do with the unbound Ctrl, Alt and Super chords.
+    pub shortcuts: Option<bindings::ShortcutPolicy>,
+    /// Whether to edit the entry with the readline like bindings or the vi mode.
+    pub editing_mode: Option<vi::EditingMode>,
+    /// Where the Tab completion candidates come from.
+    pub completer: Option<complete::CompleterConfig>,
+    /// Whether to suggest the rest of the entry from the history.
+    pub autosuggest: Option<bool>,
+    /// The marker typed before the entry.
+    pub prompt_prefix: Option<String>,
+    /// The marker typed after the entry.
+    pub prompt_suffix: Option<String>,
+    /// The marker typed before the entry in the vi normal mode.
+    pub normal_prompt_prefix: Option<String>,
+}
+
+impl Profile {
+    /// Override the settings with the ones set in `other`.
+    fn merge(mut self, other: Profile) -> Profile {
+        self.bindings.extend(other.bindings);
+        self.shortcuts = other.shortcuts.or(self.shortcuts);
+        self.editing_mode = other.editing_mode.or(self.editing_mode);
+        self.completer = other.completer.or(self.completer);
+        self.autosuggest = other.autosuggest.or(self.autosuggest);
+        self.prompt_prefix = other.prompt_prefix.or(self.prompt_prefix);
+        self.prompt_suffix = other.prompt_suffix.or(self.prompt_suffix);
+        self.normal_prompt_prefix = other.normal_prompt_prefix.or(self.normal_prompt_prefix);
+        self
+    }
+}
+
+impl Config {
+    /// Load the config file.
+    ///
+    /// If no `path` is provided, the `shiv/config.toml` file in the caller's config directory is
+    /// used, if it exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Explicit path of the config file.
+    /// * `uid` - The UID of the caller, used to locate their config directory.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the config file can't be read or parsed.
+    pub fn load(path: Option<&Path>, uid: u32) -> Result<Config, Box<dyn Error>> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path(uid)? {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        log::debug!("Loading config file: {:?}", path);
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config file {:?}: {}", path, e))?;
+        Self::parse(&contents).map_err(|e| format!("Invalid config file {:?}: {}", path, e).into())
+    }
+
+    /// Get the settings of a profile, on top of the ones of the `default` profile.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the profile, `None` for the `default` profile.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no profile with this name.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, Box<dyn Error>> {
+        let default = self
+            .profiles
+            .get(DEFAULT_PROFILE)
+            .cloned()
+            .unwrap_or_default();
+        match name {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .map(|profile| default.merge(profile.clone()))
+                .ok_or_else(|| format!("Unknown profile: {}", name).into()),
+            None => Ok(default),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(contents)
+    }
+}
+
+/// Get the default config file path of the user.
+///
+/// # Arguments
+///
+/// * `uid` - The UID of the user.
+fn default_path(uid: u32) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match utils::get_home_dir(uid)? {
+            Some(home) => home.join(".config"),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(config_dir.join("shiv").join("config.toml")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config =
+            Config::parse("[keyboard]\nlayout = \"de\"\nvariant = \"nodeadkeys\"\n").unwrap();
+        assert_eq!(config.keyboard.layout.as_deref(), Some("de"));
+        assert_eq!(config.keyboard.variant.as_deref(), Some("nodeadkeys"));
+        assert_eq!(config.keyboard.options, None);
+
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.keyboard, xkb::RuleNames::default());
+        assert_eq!(config.repeat, repeat::RepeatConfig::default());
+
+        let config = Config::parse("[devices.\"Keychron K2\"]\nlayout = \"fr\"\n").unwrap();
+        assert_eq!(config.devices["Keychron K2"].layout.as_deref(), Some("fr"));
+
+        let config = Config::parse("[history]\nsize = 0\n").unwrap();
+        assert_eq!(config.history.size, 0);
+        assert!(config.history.ignore_space);
+
+        let config = Config::parse("[repeat]\nrate = 30\n").unwrap();
+        assert_eq!(config.repeat.rate, 30);
+        assert_eq!(config.repeat.delay, repeat::RepeatConfig::default().delay);
+
+        assert!(Config::parse("[keyboard]\nlayuot = \"de\"\n").is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config = Config::parse(
+            r#"
+            [profiles.default]
+            shortcuts = "block"
+            completer = "bash"
+            prompt_suffix = ""
+
+            [profiles.default.bindings]
+            "ctrl+enter" = "submit"
+            "esc" = "clear"
+
+            [profiles.vim]
+            editing_mode = "vi"
+            autosuggest = false
+
+            [profiles.qalc]
+            completer = { command = "qalc-complete --prefix" }
+            prompt_prefix = "qalc❯ "
+
+            [profiles.vim.bindings]
+            "esc" = "none"
+            "#,
+        )
+        .unwrap();
+        let chord = |chord: &str| chord.parse::<bindings::Chord>().unwrap();
+
+        let default = config.profile(None).unwrap();
+        assert_eq!(default.bindings[&chord("esc")], bindings::Action::Clear);
+        let vim = config.profile(Some("vim")).unwrap();
+        assert_eq!(vim.bindings[&chord("esc")], bindings::Action::None);
+        assert_eq!(vim.bindings[&chord("ctrl+enter")], bindings::Action::Submit);
+        assert_eq!(vim.shortcuts, Some(bindings::ShortcutPolicy::Block));
+        assert_eq!(vim.editing_mode, Some(vi::EditingMode::Vi));
+        assert_eq!(default.editing_mode, None);
+        assert_eq!(vim.autosuggest, Some(false));
+        assert_eq!(vim.completer, Some(complete::CompleterConfig::Bash));
+        let qalc = config.profile(Some("qalc")).unwrap();
+        assert_eq!(qalc.prompt_prefix.as_deref(), Some("qalc❯ "));
+        assert_eq!(qalc.prompt_suffix.as_deref(), Some(""));
+        assert_eq!(default.prompt_prefix, None);
+        assert_eq!(
+            config.profile(Some("qalc")).unwrap().completer,
+            Some(complete::CompleterConfig::Command(
+                "qalc-complete --prefix".to_string()
+            ))
+        );
+        assert!(config.profile(Some("emacs")).is_err());
+
+        assert!(Config::parse("[profiles.default.bindings]\n\"hyper+a\" = \"submit\"").is_err());
+        assert!(Config::parse("[profiles.default.bindings]\n\"a\" = \"fly\"").is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use std::{
+    fmt::{Display, Formatter},
+    ops::{Deref, Range},
+};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// The text of the entry, split into the grapheme clusters the apps move the cursor over and
+/// delete at once, e.g. a letter with its combining marks, or an emoji sequence.
+///
+/// The positions in the entry count grapheme clusters, like the arrow presses moving the cursor.
+pub struct Entry {
+    graphemes: Vec<String>,
+}
+
+impl Entry {
+    /// Create a new [`Entry`].
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text of the entry.
+    pub fn new(text: &str) -> Entry {
+        Entry {
+            graphemes: text.graphemes(true).map(str::to_string).collect(),
+        }
+    }
+
+    /// Insert `text` at `pos`, and return the position after it.
+    ///
+    /// The text is segmented along with its neighbours, e.g. a typed combining mark joins the
+    /// grapheme cluster before it, so the returned position may be less than `pos` plus the
+    /// number of clusters of `text`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pos` - The position to insert the text at.
+    /// * `text` - The inserted text.
+    pub fn insert(&mut self, pos: usize, text: &str) -> usize {
+        let head = self.graphemes[..pos].concat() + text;
+        let joined = head.clone() + &self.graphemes[pos..].concat();
+        self.graphemes = joined.graphemes(true).map(str::to_string).collect();
+        // a cluster straddling the end of the text ends up before the cursor
+        joined
+            .grapheme_indices(true)
+            .take_while(|(i, _)| *i < head.len())
+            .count()
+    }
+
+    /// Remove the grapheme clusters in `range`, and return their text.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The positions of the removed clusters.
+    pub fn remove(&mut self, range: Range<usize>) -> String {
+        self.graphemes.drain(range).collect()
+    }
+
+    /// Remove the whole text.
+    pub fn clear(&mut self) {
+        self.graphemes.clear();
+    }
+}
+
+impl Deref for Entry {
+    type Target = [String];
+
+    fn deref(&self) -> &Self::Target {
+        &self.graphemes
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.graphemes.concat())
+    }
+}
+
+/// Whether the grapheme cluster `g` is part of a word, judging by its base character.
+fn is_word_char(g: &str) -> bool {
+    g.chars().next().is_some_and(char::is_alphanumeric)
+}
+
+fn is_blank(g: &str) -> bool {
+    g.chars().all(char::is_whitespace)
+}
+
+/// Find the start of the word before `pos`, skipping the non-word characters before it like
+/// readline's `backward-word`.
+pub fn word_start(entry: &[String], pos: usize) -> usize {
+    let mut pos = pos;
+    while pos > 0 && !is_word_char(&entry[pos - 1]) {
+        pos -= 1;
+    }
+    while pos > 0 && is_word_char(&entry[pos - 1]) {
+        pos -= 1;
+    }
+    pos
+}
+
+/// Find the end of the word after `pos`, skipping the non-word characters after it like
+/// readline's `forward-word`.
+pub fn word_end(entry: &[String], pos: usize) -> usize {
+    let mut pos = pos;
+    while pos < entry.len() && !is_word_char(&entry[pos]) {
+        pos += 1;
+    }
+    while pos < entry.len() && is_word_char(&entry[pos]) {
+        pos += 1;
+    }
+    pos
+}
+
+/// Find the start of the whitespace delimited word before `pos`, like readline's
+/// `unix-word-rubout`.
+pub fn blank_word_start(entry: &[String], pos: usize) -> usize {
+    let mut pos = pos;
+    while pos > 0 && is_blank(&entry[pos - 1]) {
+        pos -= 1;
+    }
+    while pos > 0 && !is_blank(&entry[pos - 1]) {
+        pos -= 1;
+    }
+    pos
+}
+
+/// Find the start of the line of the entry `pos` is on.
+pub fn line_start(entry: &[String], pos: usize) -> usize {
+    entry[..pos]
+        .iter()
+        .rposition(|g| g == "\n")
+        .map_or(0, |i| i + 1)
+}
+
+/// Find the end of the line of the entry `pos` is on, before its newline.
+pub fn line_end(entry: &[String], pos: usize) -> usize {
+    entry[pos..]
+        .iter()
+        .position(|g| g == "\n")
+        .map_or(entry.len(), |i| pos + i)
+}
+
+/// Find the position on the line above `pos` in the same column, or at the end of the line if
+/// it is shorter. Returns `None` on the first line.
+pub fn line_up(entry: &[String], pos: usize) -> Option<usize> {
+    let start = line_start(entry, pos);
+    if start == 0 {
+        return None;
+    }
+    let above = line_start(entry, start - 1);
+    Some((above + pos - start).min(start - 1))
+}
+
+/// Find the position on the line below `pos` in the same column, or at the end of the line if
+/// it is shorter. Returns `None` on the last line.
+pub fn line_down(entry: &[String], pos: usize) -> Option<usize> {
+    let end = line_end(entry, pos);
+    if end == entry.len() {
+        return None;
+    }
+    let column = pos - line_start(entry, pos);
+    Some((end + 1 + column).min(line_end(entry, end + 1)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_segmentation() {
+        // a combining acute accent, a family emoji joined with ZWJs, a flag and a CRLF
+        let entry = Entry::new("e\u{301}👨\u{200d}👩\u{200d}👧🇫🇷\r\nx");
+        assert_eq!(
+            *entry,
+            ["e\u{301}", "👨\u{200d}👩\u{200d}👧", "🇫🇷", "\r\n", "x"]
+        );
+        assert_eq!(entry.len(), 5);
+        assert_eq!(entry.to_string(), "e\u{301}👨\u{200d}👩\u{200d}👧🇫🇷\r\nx");
+        assert_eq!(Entry::new("").len(), 0);
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut entry = Entry::new("ab");
+        assert_eq!(entry.insert(1, "🇫🇷x"), 3);
+        assert_eq!(*entry, ["a", "🇫🇷", "x", "b"]);
+
+        // a combining mark joins the cluster before it
+        assert_eq!(entry.insert(1, "\u{301}"), 1);
+        assert_eq!(*entry, ["a\u{301}", "🇫🇷", "x", "b"]);
+
+        // and a base character joins the mark after it
+        let mut entry = Entry::new("\u{301}b");
+        assert_eq!(entry.insert(0, "e"), 1);
+        assert_eq!(*entry, ["e\u{301}", "b"]);
+
+        // the emoji sequences are joined as they are typed
+        let mut entry = Entry::new("");
+        let mut pos = 0;
+        for c in ["👨", "\u{200d}", "👩", "🇫", "🇷"] {
+            pos = entry.insert(pos, c);
+        }
+        assert_eq!(*entry, ["👨\u{200d}👩", "🇫🇷"]);
+        assert_eq!(pos, 2);
+
+        assert_eq!(entry.remove(0..1), "👨\u{200d}👩");
+        assert_eq!(*entry, ["🇫🇷"]);
+        entry.clear();
+        assert!(entry.is_empty());
+    }
+
+    #[test]
+    fn test_word_boundaries() {
+        let entry = Entry::new("ls -la ~/foo_bar  baz");
+        assert_eq!(word_start(&entry, entry.len()), 18);
+        assert_eq!(word_start(&entry, 18), 13);
+        assert_eq!(word_start(&entry, 13), 9);
+        assert_eq!(word_start(&entry, 9), 4);
+        assert_eq!(word_start(&entry, 5), 4);
+        assert_eq!(word_start(&entry, 4), 0);
+        assert_eq!(word_start(&entry, 0), 0);
+
+        assert_eq!(word_end(&entry, 0), 2);
+        assert_eq!(word_end(&entry, 2), 6);
+        assert_eq!(word_end(&entry, 6), 12);
+        assert_eq!(word_end(&entry, 13), 16);
+        assert_eq!(word_end(&entry, 16), entry.len());
+        assert_eq!(word_end(&entry, entry.len()), entry.len());
+
+        assert_eq!(blank_word_start(&entry, entry.len()), 18);
+        assert_eq!(blank_word_start(&entry, 17), 7);
+        assert_eq!(blank_word_start(&entry, 6), 3);
+        assert_eq!(blank_word_start(&entry, 0), 0);
+
+        let entry = Entry::new("héllo wörld");
+        assert_eq!(word_start(&entry, entry.len()), 6);
+        assert_eq!(word_end(&entry, 0), 5);
+
+        // decomposed accents and emoji sequences count as a single character
+        let entry = Entry::new("he\u{301}llo 👍🏽 🇫🇷 wo\u{308}rld");
+        assert_eq!(entry.len(), 15);
+        assert_eq!(word_end(&entry, 0), 5);
+        assert_eq!(word_start(&entry, entry.len()), 10);
+        assert_eq!(word_start(&entry, 10), 0);
+        assert_eq!(blank_word_start(&entry, 9), 8);
+        assert_eq!(blank_word_start(&entry, 7), 6);
+    }
+
+    #[test]
+    fn test_lines() {
+        let entry = Entry::new("foo\nbarbaz\n\nqux");
+        assert_eq!(line_start(&entry, 2), 0);
+        assert_eq!(line_start(&entry, 3), 0);
+        assert_eq!(line_start(&entry, 4), 4);
+        assert_eq!(line_start(&entry, 12), 12);
+        assert_eq!(line_end(&entry, 0), 3);
+        assert_eq!(line_end(&entry, 6), 10);
+        assert_eq!(line_end(&entry, 11), 11);
+        assert_eq!(line_end(&entry, 14), 15);
+
+        assert_eq!(line_up(&entry, 2), None);
+        assert_eq!(line_up(&entry, 9), Some(3));
+        assert_eq!(line_up(&entry, 5), Some(1));
+        assert_eq!(line_up(&entry, 11), Some(4));
+        assert_eq!(line_up(&entry, 14), Some(11));
+
+        assert_eq!(line_down(&entry, 2), Some(6));
+        assert_eq!(line_down(&entry, 9), Some(11));
+        assert_eq!(line_down(&entry, 11), Some(12));
+        assert_eq!(line_down(&entry, 13), None);
+
+        let entry = Entry::new("e\u{301}👨\u{200d}👩\u{200d}👧x\n🇫🇷");
+        assert_eq!(line_end(&entry, 0), 3);
+        assert_eq!(line_start(&entry, 5), 4);
+        assert_eq!(line_down(&entry, 2), Some(5));
+        assert_eq!(line_up(&entry, 5), Some(1));
+    }
+}
@@ -0,0 +1,549 @@
+use serde::Deserialize;
+use unicode_segmentation::UnicodeSegmentation;
+
+use std::{
+    error::Error,
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{config, utils};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// The settings of the history of the submitted entries.
+pub struct HistoryConfig {
+    /// Maximum number of entries to keep, 0 disables the history.
+    pub size: usize,
+    /// Don't save the entries starting with a space, like bash's `ignorespace`.
+    pub ignore_space: bool,
+    /// Remove the older copies of a saved entry, like bash's `erasedups`.
+    pub erase_dups: bool,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            size: 1000,
+            ignore_space: true,
+            erase_dups: true,
+        }
+    }
+}
+
+/// Escape the backslashes and newlines of an entry, to save it on a single line.
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Revert [`escape`].
+fn unescape(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            entry.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => entry.push('\n'),
+            Some(c) => entry.push(c),
+            None => entry.push('\\'),
+        }
+    }
+    entry
+}
+
+/// Read the entries of a history file, the oldest first.
+fn read_entries(path: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read history file {:?}: {}", path, e))?;
+    Ok(contents.lines().map(unescape).collect())
+}
+
+/// Get the history file path of a profile and pre command.
+///
+/// The files are in the `shiv/history` directory of the user's state directory.
+///
+/// # Arguments
+///
+/// * `uid` - The UID of the user.
+/// * `profile` - The name of the profile, `None` for the `default` profile.
+/// * `pre_cmd` - The command the entries are passed to.
+fn default_path(
+    uid: u32,
+    profile: Option<&str>,
+    pre_cmd: &str,
+) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match utils::get_home_dir(uid)? {
+            Some(home) => home.join(".local").join("state"),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(history_path(&state_dir, profile, pre_cmd)))
+}
+
+/// Hash the `text` with the 64-bit FNV-1a function, which unlike the std hasher is stable across
+/// Rust versions.
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Get the history file path of a profile and pre command, in the `state_dir`.
+///
+/// The file is named after the profile and a hash of the pre command, which is neither too long
+/// for a file name nor shared by commands differing only in their special characters.
+///
+/// # Arguments
+///
+/// * `state_dir` - The user's state directory.
+/// * `profile` - The name of the profile, `None` for the `default` profile.
+/// * `pre_cmd` - The command the entries are passed to.
+fn history_path(state_dir: &Path, profile: Option<&str>, pre_cmd: &str) -> PathBuf {
+    let profile: String = profile
+        .unwrap_or(config::DEFAULT_PROFILE)
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    let name = format!("{}-{:016x}", profile, fnv1a(pre_cmd));
+    state_dir.join("shiv").join("history").join(name)
+}
+
+#[derive(Debug, Default, Clone)]
+/// The submitted entries, to recall them with Up and Down.
+pub struct History {
+    /// The entries, the oldest first.
+    entries: Vec<String>,
+    config: HistoryConfig,
+    /// The file the history is saved to, `None` to only keep it in memory.
+    path: Option<PathBuf>,
+    /// The index of the recalled entry, `entries.len()` while editing a new entry.
+    index: usize,
+    /// The new entry, while older ones are recalled.
+    draft: Option<String>,
+}
+
+impl History {
+    /// Create a new [`History`].
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The [`HistoryConfig`] to use.
+    /// * `path` - The file to load the entries from and save them to.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be read.
+    pub fn new(config: HistoryConfig, path: Option<PathBuf>) -> Result<History, Box<dyn Error>> {
+        let path = path.filter(|_| config.size > 0);
+        let mut entries = match &path {
+            Some(path) => read_entries(path)?,
+            None => Vec::new(),
+        };
+        entries.drain(..entries.len().saturating_sub(config.size));
+        Ok(History {
+            index: entries.len(),
+            entries,
+            config,
+            path,
+            draft: None,
+        })
+    }
+
+    /// Load the history of a profile and pre command, from the user's state directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The [`HistoryConfig`] to use.
+    /// * `uid` - The UID of the user.
+    /// * `profile` - The name of the profile, `None` for the `default` profile.
+    /// * `pre_cmd` - The command the entries are passed to.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the history file can't be read.
+    pub fn load(
+        config: HistoryConfig,
+        uid: u32,
+        profile: Option<&str>,
+        pre_cmd: &str,
+    ) -> Result<History, Box<dyn Error>> {
+        let path = default_path(uid, profile, pre_cmd)?;
+        log::debug!("History file: {:?}", path);
+        History::new(config, path)
+    }
+
+    /// Get the entry before the recalled one.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - The entry being edited, which is recalled again after the most recent entry.
+    pub fn previous(&mut self, current: &str) -> Option<String> {
+        if self.index == 0 {
+            return None;
+        }
+        if self.index == self.entries.len() {
+            self.draft = Some(current.to_string());
+        }
+        self.index -= 1;
+        Some(self.entries[self.index].clone())
+    }
+
+    /// Get the entry after the recalled one, or the new entry after the most recent one.
+    pub fn next(&mut self) -> Option<String> {
+        if self.index >= self.entries.len() {
+            return None;
+        }
+        self.index += 1;
+        match self.entries.get(self.index) {
+            Some(entry) => Some(entry.clone()),
+            None => Some(self.draft.take().unwrap_or_default()),
+        }
+    }
+
+    /// Add a submitted entry and save the history.
+    ///
+    /// The file is read again first, to keep the entries the other sessions added meanwhile.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The submitted entry.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the history file can't be read or written.
+    pub fn add(&mut self, entry: &str) -> Result<(), Box<dyn Error>> {
+        let ignored = entry.trim().is_empty()
+            || (self.config.ignore_space && entry.starts_with(' '))
+            || self.config.size == 0;
+        if ignored {
+            return Ok(());
+        }
+        if let Some(path) = &self.path {
+            self.entries = read_entries(path)?;
+        }
+        if self.config.erase_dups {
+            self.entries.retain(|other| other != entry);
+        }
+        if self.entries.last().map(String::as_str) != Some(entry) {
+            self.entries.push(entry.to_string());
+        }
+        self.entries
+            .drain(..self.entries.len().saturating_sub(self.config.size));
+        self.index = self.entries.len();
+        self.draft = None;
+        self.save()
+    }
+
+    /// Get the most recent entry which starts with `prefix` and is longer.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The start of the entry, suggestions need a non blank one.
+    pub fn suggest(&self, prefix: &str) -> Option<&str> {
+        if prefix.trim().is_empty() {
+            return None;
+        }
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.len() > prefix.len() && entry.starts_with(prefix))
+            .map(String::as_str)
+    }
+
+    /// Find the most recent entry containing `query`, among the ones before `before`.
+    fn find(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+
+    /// Write the entries to the history file.
+    ///
+    /// The file is only readable by the user, like bash's `HISTFILE`. It is written to a
+    /// temporary file first, which replaces it at once, so that concurrent sessions don't read or
+    /// write a partial file.
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let dir = path.parent().ok_or("Invalid history file path")?;
+        let name = path.file_name().ok_or("Invalid history file path")?;
+        std::fs::create_dir_all(dir)?;
+        let contents: String = self
+            .entries
+            .iter()
+            .map(|entry| escape(entry) + "\n")
+            .collect();
+        let temp_path = dir.join(format!(
+            ".{}.{}.tmp",
+            name.to_string_lossy(),
+            std::process::id()
+        ));
+        let written = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temp_path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .and_then(|_| std::fs::rename(&temp_path, path));
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(format!("Failed to write history file {:?}: {}", path, e).into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+/// A reverse incremental search of the [`History`], like readline's `reverse-search-history`.
+pub struct Search {
+    query: String,
+    /// The index of the matching entry.
+    found: Option<usize>,
+    /// Whether the last search found nothing, the previous match is kept.
+    failed: bool,
+}
+
+impl Search {
+    /// Add `text` to the query, keeping the current match if it still matches.
+    ///
+    /// # Arguments
+    ///
+    /// * `history` - The searched [`History`].
+    /// * `text` - The typed text.
+    pub fn push(&mut self, history: &History, text: &str) {
+        self.query.push_str(text);
+        let before = self.found.map_or(history.entries.len(), |i| i + 1);
+        self.search(history, before);
+    }
+
+    /// Remove the last character of the query, and search again from the most recent entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `history` - The searched [`History`].
+    pub fn pop(&mut self, history: &History) {
+        self.query.pop();
+        self.found = None;
+        self.failed = false;
+        self.search(history, history.entries.len());
+    }
+
+    /// Find the next older match.
+    ///
+    /// # Arguments
+    ///
+    /// * `history` - The searched [`History`].
+    pub fn older(&mut self, history: &History) {
+        let before = self.found.unwrap_or(history.entries.len());
+        self.search(history, before);
+    }
+
+    fn search(&mut self, history: &History, before: usize) {
+        if self.query.is_empty() {
+            return;
+        }
+        match history.find(&self.query, before) {
+            Some(i) => {
+                self.found = Some(i);
+                self.failed = false;
+            }
+            None => self.failed = true,
+        }
+    }
+
+    /// Get the matching entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `history` - The searched [`History`].
+    pub fn matched<'a>(&self, history: &'a History) -> Option<&'a str> {
+        self.found
+            .and_then(|i| history.entries.get(i))
+            .map(String::as_str)
+    }
+
+    /// Render the search like readline does, to be typed in place of the entry.
+    ///
+    /// Returns the text and the position of the cursor in it, at the match of the query, in
+    /// grapheme clusters.
+    ///
+    /// # Arguments
+    ///
+    /// * `history` - The searched [`History`].
+    pub fn render(&self, history: &History) -> (String, usize) {
+        let status = if self.failed {
+            "failed reverse-i-search"
+        } else {
+            "reverse-i-search"
+        };
+        let head = format!("({})`{}': ", status, self.query);
+        let matched = self.matched(history).unwrap_or_default();
+        let cursor = head.len() + matched.find(&self.query).unwrap_or(0);
+        let text = head + matched;
+        let pos = text[..cursor].graphemes(true).count();
+        (text, pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_escape() {
+        for entry in ["echo foo", "print('a\\nb')\nprint(1)", "\\", "a\\\\n"] {
+            assert_eq!(unescape(&escape(entry)), entry);
+            assert!(!escape(entry).contains('\n'));
+        }
+        assert_eq!(escape("a\nb\\n"), "a\\nb\\\\n");
+    }
+
+    #[test]
+    fn test_history_recall() {
+        let mut history = History::new(HistoryConfig::default(), None).unwrap();
+        assert_eq!(history.previous("foo"), None);
+        assert_eq!(history.next(), None);
+
+        for entry in ["ls", "pwd", " secret", "", "ls", "ls", "date"] {
+            history.add(entry).unwrap();
+        }
+        assert_eq!(history.entries, ["pwd", "ls", "date"]);
+
+        assert_eq!(history.previous("ec").as_deref(), Some("date"));
+        assert_eq!(history.previous("date").as_deref(), Some("ls"));
+        assert_eq!(history.previous("ls").as_deref(), Some("pwd"));
+        assert_eq!(history.previous("pwd"), None);
+        assert_eq!(history.next().as_deref(), Some("ls"));
+        assert_eq!(history.next().as_deref(), Some("date"));
+        assert_eq!(history.next().as_deref(), Some("ec"));
+        assert_eq!(history.next(), None);
+
+        let config = HistoryConfig {
+            size: 2,
+            ignore_space: false,
+            erase_dups: false,
+        };
+        let mut history = History::new(config, None).unwrap();
+        for entry in ["ls", " ls", "ls", "ls", "pwd"] {
+            history.add(entry).unwrap();
+        }
+        assert_eq!(history.entries, ["ls", "pwd"]);
+    }
+
+    #[test]
+    fn test_suggest() {
+        let mut history = History::new(HistoryConfig::default(), None).unwrap();
+        for entry in ["echo foo", "echo bar", "ls"] {
+            history.add(entry).unwrap();
+        }
+        assert_eq!(history.suggest("ec"), Some("echo bar"));
+        assert_eq!(history.suggest("echo f"), Some("echo foo"));
+        assert_eq!(history.suggest("ls"), None);
+        assert_eq!(history.suggest(" "), None);
+        assert_eq!(history.suggest(""), None);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut history = History::new(HistoryConfig::default(), None).unwrap();
+        for entry in ["echo foo", "ls", "echo bar", "cat foo", "pwd"] {
+            history.add(entry).unwrap();
+        }
+        let mut search = Search::default();
+        assert_eq!(
+            search.render(&history),
+            ("(reverse-i-search)`': ".to_string(), 22)
+        );
+        search.older(&history);
+        assert_eq!(search.matched(&history), None);
+
+        search.push(&history, "o");
+        assert_eq!(search.matched(&history), Some("cat foo"));
+        search.push(&history, "o");
+        assert_eq!(search.matched(&history), Some("cat foo"));
+        assert_eq!(
+            search.render(&history),
+            ("(reverse-i-search)`oo': cat foo".to_string(), 29)
+        );
+        search.older(&history);
+        assert_eq!(search.matched(&history), Some("echo foo"));
+        // no older match, the last one is kept
+        search.older(&history);
+        assert_eq!(search.matched(&history), Some("echo foo"));
+        assert_eq!(
+            search.render(&history).0,
+            "(failed reverse-i-search)`oo': echo foo"
+        );
+
+        search.pop(&history);
+        assert_eq!(search.matched(&history), Some("cat foo"));
+        search.push(&history, " b");
+        assert_eq!(search.matched(&history), Some("echo bar"));
+        search.push(&history, "z");
+        assert_eq!(search.matched(&history), Some("echo bar"));
+        assert!(search.failed);
+    }
+
+    #[test]
+    fn test_history_file() {
+        let dir = std::env::temp_dir().join(format!("shiv-test-history-{}", std::process::id()));
+        let path = dir.join("shiv").join("default-bash_-c");
+
+        let mut history = History::new(HistoryConfig::default(), Some(path.clone())).unwrap();
+        history.add("echo 'a\nb'").unwrap();
+        let mut other = History::new(HistoryConfig::default(), Some(path.clone())).unwrap();
+        other.add("date").unwrap();
+        // the entries added by the other session are kept
+        history.add("ls").unwrap();
+
+        let history = History::new(HistoryConfig::default(), Some(path.clone())).unwrap();
+        assert_eq!(history.entries, ["echo 'a\nb'", "date", "ls"]);
+        // the file is private, and the temporary file is gone
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            1
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_history_path() {
+        assert_eq!(fnv1a(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a("a"), 0xaf63_dc4c_8601_ec8c);
+
+        let state_dir = Path::new("/tmp/state");
+        assert_eq!(
+            history_path(state_dir, None, "python -c"),
+            PathBuf::from("/tmp/state/shiv/history/default-4cb6e42b6a3de851")
+        );
+        assert_eq!(
+            history_path(state_dir, Some("g/p t"), "sgpt"),
+            PathBuf::from("/tmp/state/shiv/history/g_p_t-1b82a118c9fd440b")
+        );
+        // the commands differing only in their special characters don't share a file
+        assert_ne!(
+            history_path(state_dir, None, "python -c"),
+            history_path(state_dir, None, "python_-c")
+        );
+        let long = history_path(state_dir, None, &"x".repeat(1000));
+        assert!(long.file_name().unwrap().len() < 255);
+    }
+}
@@ -1,13 +1,41 @@
-use evdev::{AttributeSet, InputEvent, Key};
+use evdev::{AttributeSet, InputEvent, Key, LedType};
 
-use std::{collections::HashSet, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug};
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+use crate::{compose, xkb};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
 pub enum Modifier {
     Super,
     Alt,
     Control,
     Shift,
+    /// The level 3 shift, i.e. AltGr.
+    AltGr,
+    /// The level 5 shift.
+    Level5,
+}
+
+impl Modifier {
+    /// Whether the modifier selects which character a key produces, as opposed to the modifiers
+    /// used for shortcuts.
+    pub fn is_level(&self) -> bool {
+        matches!(self, Modifier::Shift | Modifier::AltGr | Modifier::Level5)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+/// The text produced by a key press.
+pub enum KeyText {
+    /// The key doesn't produce text.
+    None,
+    /// The key is part of a dead key or Compose sequence, which either continues or got cancelled.
+    Composing,
+    /// The key produces this text.
+    Text(String),
+    /// The key is a dead key which doesn't start a sequence, e.g. without a Compose table, and
+    /// types this text on its own in place of the key.
+    Spacing(String),
 }
 
 fn evdev_modifier_to_enum(key: Key) -> Option<Modifier> {
@@ -20,76 +48,289 @@ fn evdev_modifier_to_enum(key: Key) -> Option<Modifier> {
     }
 }
 
+#[derive(Debug)]
+/// The state of one of the grabbed keyboard devices.
+struct DeviceState {
+    /// The held modifier keys.
+    modifiers: HashMap<Key, Modifier>,
+    /// The keys which act as level shifts in the layout, e.g. AltGr.
+    level_keys: HashMap<Key, Modifier>,
+    /// The keys which repeat when held.
+    repeat_keys: AttributeSet<Key>,
+    state: xkb::State,
+}
+
+impl DeviceState {
+    fn new(keymap: &xkb::Keymap) -> DeviceState {
+        let keys: Vec<Key> = (0..=255).map(Key::new).collect();
+        let level_keys = keymap
+            .level3_keys(&keys)
+            .into_iter()
+            .map(|key| (key, Modifier::AltGr))
+            .chain(
+                keymap
+                    .level5_keys(&keys)
+                    .into_iter()
+                    .map(|key| (key, Modifier::Level5)),
+            )
+            .collect();
+        let repeat_keys = keymap.repeating_keys(&keys).into_iter().collect();
+        DeviceState {
+            modifiers: HashMap::new(),
+            level_keys,
+            repeat_keys,
+            state: xkb::State::new(keymap),
+        }
+    }
+
+    fn handle_event(&mut self, event: InputEvent, key: Key) {
+        // repeats don't change the xkb state
+        if event.value() != 2 {
+            self.state.update_key(key, event.value() == 1);
+        }
+        if let Some(modifier) = self
+            .level_keys
+            .get(&key)
+            .copied()
+            .or_else(|| evdev_modifier_to_enum(key))
+        {
+            self.update_modifiers(event, key, modifier);
+        }
+    }
+
+    fn update_modifiers(&mut self, event: InputEvent, key: Key, modifier: Modifier) {
+        if event.value() == 0 {
+            // Key is released
+            self.modifiers.remove(&key);
+        } else if event.value() == 1 {
+            // Key is pressed
+            self.modifiers.insert(key, modifier);
+        }
+    }
+
+    /// Get the keysym and character of the `key`, as if the `others` modifier keys, held on the
+    /// other devices, were held on this one too.
+    fn decode(&mut self, key: Key, others: &[Key]) -> (u32, Option<char>) {
+        let others: Vec<Key> = others
+            .iter()
+            .copied()
+            .filter(|other| !self.modifiers.contains_key(other))
+            .collect();
+        others
+            .iter()
+            .for_each(|other| self.state.update_key(*other, true));
+        let decoded = (
+            self.state.key_get_one_sym(key),
+            self.state.key_get_char(key),
+        );
+        others
+            .iter()
+            .rev()
+            .for_each(|other| self.state.update_key(*other, false));
+        decoded
+    }
+}
+
 #[derive(Debug)]
 /// Keyboard state.
-/// Keeps track of the current state of the keyboard's keys and modifiers.
+/// Keeps track of the current state of the grabbed keyboards' keys and modifiers.
+///
+/// Each device is decoded with its own layout, while the modifiers, locks and Compose sequences
+/// are shared between the devices, as they are by the display server.
 pub struct Keyboard {
-    modifiers: HashSet<Modifier>,
-    keysyms: AttributeSet<evdev::Key>,
+    devices: HashMap<usize, DeviceState>,
+    /// The held modifier keys of all the devices.
+    modifiers: HashMap<Key, Modifier>,
+    compose: Option<compose::ComposeState>,
 }
 
 impl Keyboard {
-    /// Create a new [`Keyboard`].
+    /// Create a new [`Keyboard`], without any device.
     ///
     /// # Arguments
     ///
-    /// * `device` - The virtual device to emit events to.
-    pub fn new() -> Keyboard {
+    /// * `compose` - The [`compose::Table`] of dead key and Compose sequences, if any.
+    pub fn new(compose: Option<compose::Table>) -> Keyboard {
         Keyboard {
-            modifiers: HashSet::new(),
-            keysyms: AttributeSet::new(),
+            devices: HashMap::new(),
+            modifiers: HashMap::new(),
+            compose: compose.map(compose::ComposeState::new),
         }
     }
 
-    pub fn handle_event(&mut self, event: InputEvent, key: Key) {
-        if let Some(modifier) = evdev_modifier_to_enum(key) {
-            self.update_modifiers(event, modifier);
-        } else {
-            self.update_keysyms(event, key);
+    /// Add a keyboard device.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The index of the device, as passed to [`Keyboard::handle_event`].
+    /// * `keymap` - The [`xkb::Keymap`] used to decode the device's keys.
+    pub fn add_device(&mut self, device: usize, keymap: &xkb::Keymap) {
+        let mut state = DeviceState::new(keymap);
+        // the locks are shared by all the devices
+        if let Some(other) = self.devices.values().next() {
+            for lock in [xkb::Lock::Caps, xkb::Lock::Num] {
+                state.state.set_lock(lock, other.state.is_locked(lock));
+            }
         }
+        self.devices.insert(device, state);
     }
 
-    fn update_keysyms(&mut self, event: InputEvent, key: Key) {
-        if event.value() == 0 {
-            // Key is released
-            self.keysyms.remove(key);
-        } else if event.value() == 1 {
-            // Key is pressed
-            self.keysyms.insert(key);
+    /// Update the state of the `device` with a key event.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The index of the device the event comes from.
+    /// * `event` - The key [`InputEvent`].
+    /// * `key` - The [`Key`] of the event.
+    pub fn handle_event(&mut self, device: usize, event: InputEvent, key: Key) {
+        let Some(state) = self.devices.get_mut(&device) else {
+            log::warn!("Event from unknown device: {}", device);
+            return;
+        };
+        let locks = [xkb::Lock::Caps, xkb::Lock::Num].map(|lock| state.state.is_locked(lock));
+        state.handle_event(event, key);
+        // sync the locks the key toggled to the other devices
+        for (lock, locked) in [xkb::Lock::Caps, xkb::Lock::Num].into_iter().zip(locks) {
+            if self.devices[&device].state.is_locked(lock) != locked {
+                self.set_lock(lock, !locked);
+            }
         }
+        self.modifiers = self
+            .devices
+            .values()
+            .flat_map(|state| state.modifiers.iter().map(|(k, m)| (*k, *m)))
+            .collect();
     }
 
-    fn update_modifiers(&mut self, event: InputEvent, modifier: Modifier) {
-        if event.value() == 0 {
-            // Key is released
-            self.modifiers.remove(&modifier);
-        } else if event.value() == 1 {
-            // Key is pressed
-            self.modifiers.insert(modifier);
+    /// Update the lock state from the state of a keyboard LED, e.g. the Caps Lock LED.
+    ///
+    /// The lock keys toggle the locks on their own, the LEDs keep them in sync with the other
+    /// keyboards and the state they had before `shiv` started.
+    ///
+    /// # Arguments
+    ///
+    /// * `led` - The [`LedType`] which changed.
+    /// * `on` - Whether the LED is on.
+    pub fn set_led(&mut self, led: LedType, on: bool) {
+        let lock = match led {
+            LedType::LED_CAPSL => xkb::Lock::Caps,
+            LedType::LED_NUML => xkb::Lock::Num,
+            _ => return,
+        };
+        if self.is_locked(lock) != on {
+            log::debug!("{:?} lock: {}", lock, on);
+            self.set_lock(lock, on);
         }
     }
 
-    // helper functions
-    pub fn is_ctrl_c(&self) -> bool {
-        self.is_ctrl() && self.keysyms.contains(Key::KEY_C)
+    fn set_lock(&mut self, lock: xkb::Lock, active: bool) {
+        self.devices
+            .values_mut()
+            .filter(|state| state.state.is_locked(lock) != active)
+            .for_each(|state| state.state.set_lock(lock, active));
     }
-    pub fn is_enter(&self) -> bool {
-        self.keysyms.contains(Key::KEY_ENTER)
+
+    /// Whether the `lock` is active.
+    pub fn is_locked(&self, lock: xkb::Lock) -> bool {
+        self.devices
+            .values()
+            .any(|state| state.state.is_locked(lock))
     }
-    pub fn is_escape(&self) -> bool {
-        self.keysyms.contains(Key::KEY_ESC)
+
+    /// Get the text a key press produces given the current state of the keyboard.
+    ///
+    /// This should be called before the key press is passed to [`Keyboard::handle_event`].
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The index of the device the key was pressed on.
+    /// * `key` - The pressed [`Key`].
+    pub fn key_text(&mut self, device: usize, key: Key) -> KeyText {
+        let Some((keysym, c)) = self.decode(device, key) else {
+            return KeyText::None;
+        };
+        if let (Some(compose), true) = (&mut self.compose, keysym != 0) {
+            match compose.feed(keysym) {
+                compose::Status::Nothing => {}
+                compose::Status::Composing | compose::Status::Cancelled => {
+                    return KeyText::Composing
+                }
+                compose::Status::Composed(text) => return KeyText::Text(text),
+            }
+        }
+        // otherwise the app couldn't compose it either, nor the entry follow what it does
+        if let Some(c) = xkb::dead_key_char(keysym) {
+            return KeyText::Spacing(c.to_string());
+        }
+        match c {
+            Some(c) => KeyText::Text(c.to_string()),
+            None => KeyText::None,
+        }
     }
 
-    // modifier query
-    pub fn is_ctrl(&self) -> bool {
-        self.modifiers.contains(&Modifier::Control)
+    /// Get the text a key press produces on its own, without feeding the Compose sequences.
+    ///
+    /// This is the text of the key's repeats: the app's Compose sequence is over once the key
+    /// completed it, so the held key repeats its own character.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The index of the device the key was pressed on.
+    /// * `key` - The pressed [`Key`].
+    pub fn plain_text(&mut self, device: usize, key: Key) -> KeyText {
+        match self.decode(device, key) {
+            Some((_, Some(c))) => KeyText::Text(c.to_string()),
+            _ => KeyText::None,
+        }
     }
-    pub fn is_shift(&self) -> bool {
-        self.modifiers.contains(&Modifier::Shift)
+
+    /// Decode the keysym and character of a key press, with the modifiers of all the devices.
+    fn decode(&mut self, device: usize, key: Key) -> Option<(u32, Option<char>)> {
+        let others: Vec<Key> = self
+            .devices
+            .iter()
+            .filter(|(index, _)| **index != device)
+            .flat_map(|(_, state)| state.modifiers.keys().copied())
+            .collect();
+        let state = self.devices.get_mut(&device)?;
+        Some(state.decode(key, &others))
+    }
+
+    /// Get the text a key event produces, see [`Keyboard::key_text`].
+    ///
+    /// Only the presses are decoded. The releases and the kernel's repeats produce no text, and
+    /// don't feed the Compose sequences, as the app only receives the presses.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The index of the device the key event comes from.
+    /// * `key` - The [`Key`] of the event.
+    /// * `value` - The value of the event: 0 for a release, 1 for a press, 2 for a repeat.
+    pub fn event_text(&mut self, device: usize, key: Key, value: i32) -> KeyText {
+        if value == 1 {
+            self.key_text(device, key)
+        } else {
+            KeyText::None
+        }
+    }
+
+    /// Whether the `key` of the `device` repeats when held.
+    pub fn key_repeats(&self, device: usize, key: Key) -> bool {
+        self.devices
+            .get(&device)
+            .is_some_and(|state| state.repeat_keys.contains(key))
+    }
+
+    /// The held modifier keys of all the devices and the [`Modifier`] they act as.
+    pub fn modifiers(&self) -> &HashMap<Key, Modifier> {
+        &self.modifiers
+    }
+
+    // modifier query
+    #[cfg(test)]
+    fn has_modifier(&self, modifier: Modifier) -> bool {
+        self.modifiers.values().any(|m| *m == modifier)
     }
-    // pub fn is_alt(&self) -> bool {
-    //     self.modifiers.contains(&Modifier::Alt)
-    // }
 }
 
 #[cfg(test)]
@@ -99,32 +340,218 @@ mod test {
     use evdev::InputEvent;
     use evdev::Key;
 
+    fn text(text: &str) -> KeyText {
+        KeyText::Text(text.to_string())
+    }
+
+    /// A [`Keyboard`] with a single device, 0, using the `layout` test keymap.
+    fn keyboard_with(layout: &str, compose: Option<compose::Table>) -> Keyboard {
+        let mut keyboard = Keyboard::new(compose);
+        keyboard.add_device(0, &xkb::test_keymap(layout));
+        keyboard
+    }
+
+    fn press(keyboard: &mut Keyboard, device: usize, key: Key, value: i32) {
+        let event = InputEvent::new(EventType::KEY, key.code(), value);
+        keyboard.handle_event(device, event, key);
+    }
+
     #[test]
     fn test_keyboard_modifiers() {
-        let mut keyboard = Keyboard::new();
+        let mut keyboard = keyboard_with("us", None);
         let event = InputEvent::new(EventType::KEY, Key::KEY_LEFTCTRL.code(), 1);
-        keyboard.handle_event(event, Key::KEY_LEFTCTRL);
-        assert!(keyboard.is_ctrl());
-        assert!(!keyboard.is_shift());
-        assert!(!keyboard.is_ctrl_c());
-        assert!(!keyboard.is_enter());
-        assert!(!keyboard.is_escape());
+        keyboard.handle_event(0, event, Key::KEY_LEFTCTRL);
+        assert!(keyboard.has_modifier(Modifier::Control));
+        assert!(!keyboard.has_modifier(Modifier::Shift));
 
         let event = InputEvent::new(EventType::KEY, Key::KEY_LEFTCTRL.code(), 0);
-        keyboard.handle_event(event, Key::KEY_LEFTCTRL);
-        assert!(!keyboard.is_ctrl());
+        keyboard.handle_event(0, event, Key::KEY_LEFTCTRL);
+        assert!(!keyboard.has_modifier(Modifier::Control));
     }
 
     #[test]
-    fn test_keyboard_keysyms() {
-        let mut keyboard = Keyboard::new();
-        let event = InputEvent::new(EventType::KEY, Key::KEY_C.code(), 1);
-        keyboard.handle_event(event, Key::KEY_C);
-        assert!(keyboard.keysyms.contains(Key::KEY_C));
-        assert!(!keyboard.keysyms.contains(Key::KEY_ENTER));
-        assert!(!keyboard.keysyms.contains(Key::KEY_ESC));
-        let event = InputEvent::new(EventType::KEY, Key::KEY_C.code(), 0);
-        keyboard.handle_event(event, Key::KEY_C);
-        assert!(!keyboard.keysyms.contains(Key::KEY_C));
+    fn test_keyboard_repeats() {
+        let keyboard = keyboard_with("us", None);
+        assert!(keyboard.key_repeats(0, Key::KEY_C));
+        assert!(!keyboard.key_repeats(0, Key::KEY_LEFTCTRL));
+        assert!(!keyboard.key_repeats(1, Key::KEY_C));
+    }
+
+    #[test]
+    fn test_keyboard_both_shifts() {
+        let mut keyboard = keyboard_with("us", None);
+        for (key, value) in [(Key::KEY_LEFTSHIFT, 1), (Key::KEY_RIGHTSHIFT, 1)] {
+            keyboard.handle_event(0, InputEvent::new(EventType::KEY, key.code(), value), key);
+        }
+        let event = InputEvent::new(EventType::KEY, Key::KEY_RIGHTSHIFT.code(), 0);
+        keyboard.handle_event(0, event, Key::KEY_RIGHTSHIFT);
+        // the left shift is still held
+        assert!(keyboard.has_modifier(Modifier::Shift));
+        assert_eq!(keyboard.key_text(0, Key::KEY_A), text("A"));
+    }
+
+    #[test]
+    fn test_keyboard_altgr() {
+        let mut keyboard = keyboard_with("de", None);
+        let event = InputEvent::new(EventType::KEY, Key::KEY_RIGHTALT.code(), 1);
+        keyboard.handle_event(0, event, Key::KEY_RIGHTALT);
+        assert!(keyboard.has_modifier(Modifier::AltGr));
+        assert!(!keyboard.has_modifier(Modifier::Alt));
+        assert_eq!(
+            keyboard.modifiers().get(&Key::KEY_RIGHTALT),
+            Some(&Modifier::AltGr)
+        );
+        assert_eq!(keyboard.key_text(0, Key::KEY_Q), text("@"));
+        assert_eq!(keyboard.key_text(0, Key::KEY_7), text("{"));
+
+        // the right alt is a plain alt in the us layout
+        let mut keyboard = keyboard_with("us", None);
+        keyboard.handle_event(0, event, Key::KEY_RIGHTALT);
+        assert!(keyboard.has_modifier(Modifier::Alt));
+        assert!(!keyboard.has_modifier(Modifier::AltGr));
+    }
+
+    #[test]
+    fn test_keyboard_level5() {
+        let mut keyboard = keyboard_with("de-neo", None);
+        let event = InputEvent::new(EventType::KEY, Key::KEY_102ND.code(), 1);
+        keyboard.handle_event(0, event, Key::KEY_102ND);
+        assert_eq!(
+            keyboard.modifiers().get(&Key::KEY_102ND),
+            Some(&Modifier::Level5)
+        );
+        assert_eq!(keyboard.key_text(0, Key::KEY_6), text("£"));
+    }
+
+    #[test]
+    fn test_keyboard_key_text() {
+        let mut keyboard = keyboard_with("fr", None);
+        assert_eq!(keyboard.key_text(0, Key::KEY_Q), text("a"));
+        let event = InputEvent::new(EventType::KEY, Key::KEY_LEFTSHIFT.code(), 1);
+        keyboard.handle_event(0, event, Key::KEY_LEFTSHIFT);
+        assert_eq!(keyboard.key_text(0, Key::KEY_Q), text("A"));
+        assert_eq!(keyboard.key_text(0, Key::KEY_1), text("1"));
+        let event = InputEvent::new(EventType::KEY, Key::KEY_LEFTSHIFT.code(), 0);
+        keyboard.handle_event(0, event, Key::KEY_LEFTSHIFT);
+        assert_eq!(keyboard.key_text(0, Key::KEY_1), text("&"));
+    }
+
+    #[test]
+    fn test_keyboard_repeat_text() {
+        let mut keyboard = keyboard_with("us", None);
+        press(&mut keyboard, 0, Key::KEY_LEFTSHIFT, 1);
+        assert_eq!(keyboard.key_text(0, Key::KEY_A), text("A"));
+        press(&mut keyboard, 0, Key::KEY_A, 1);
+        assert_eq!(keyboard.plain_text(0, Key::KEY_A), text("A"));
+        // releasing shift while the key is held changes the text of its next repeats
+        press(&mut keyboard, 0, Key::KEY_LEFTSHIFT, 0);
+        assert_eq!(keyboard.plain_text(0, Key::KEY_A), text("a"));
+        press(&mut keyboard, 0, Key::KEY_LEFTSHIFT, 1);
+        assert_eq!(keyboard.plain_text(0, Key::KEY_A), text("A"));
+    }
+
+    #[test]
+    fn test_keyboard_locks() {
+        let mut keyboard = keyboard_with("us", None);
+        keyboard.set_led(LedType::LED_CAPSL, true);
+        assert!(keyboard.is_locked(xkb::Lock::Caps));
+        assert_eq!(keyboard.key_text(0, Key::KEY_A), text("A"));
+        assert_eq!(keyboard.key_text(0, Key::KEY_KP7), KeyText::None);
+
+        keyboard.set_led(LedType::LED_NUML, true);
+        assert_eq!(keyboard.key_text(0, Key::KEY_KP7), text("7"));
+        assert_eq!(keyboard.key_text(0, Key::KEY_KPASTERISK), text("*"));
+        keyboard.set_led(LedType::LED_SCROLLL, true);
+        assert!(keyboard.is_locked(xkb::Lock::Num));
+
+        // pressing caps lock toggles it off
+        for value in [1, 0] {
+            let event = InputEvent::new(EventType::KEY, Key::KEY_CAPSLOCK.code(), value);
+            keyboard.handle_event(0, event, Key::KEY_CAPSLOCK);
+        }
+        assert!(!keyboard.is_locked(xkb::Lock::Caps));
+        assert_eq!(keyboard.key_text(0, Key::KEY_A), text("a"));
+        // the LED event which follows doesn't toggle it again
+        keyboard.set_led(LedType::LED_CAPSL, false);
+        assert!(!keyboard.is_locked(xkb::Lock::Caps));
+    }
+
+    #[test]
+    fn test_keyboard_devices() {
+        let mut keyboard = Keyboard::new(None);
+        keyboard.add_device(0, &xkb::test_keymap("us"));
+        keyboard.add_device(1, &xkb::test_keymap("de"));
+
+        // each device is decoded with its own layout
+        assert_eq!(keyboard.key_text(0, Key::KEY_Y), text("y"));
+        assert_eq!(keyboard.key_text(1, Key::KEY_Y), text("z"));
+
+        // the modifiers apply across devices
+        press(&mut keyboard, 0, Key::KEY_LEFTSHIFT, 1);
+        assert!(keyboard.has_modifier(Modifier::Shift));
+        assert_eq!(keyboard.key_text(1, Key::KEY_Y), text("Z"));
+        press(&mut keyboard, 1, Key::KEY_RIGHTALT, 1);
+        assert_eq!(
+            keyboard.modifiers().get(&Key::KEY_RIGHTALT),
+            Some(&Modifier::AltGr)
+        );
+        press(&mut keyboard, 1, Key::KEY_RIGHTALT, 0);
+
+        // releasing shift on the other device doesn't release it
+        press(&mut keyboard, 1, Key::KEY_LEFTSHIFT, 1);
+        press(&mut keyboard, 1, Key::KEY_LEFTSHIFT, 0);
+        assert!(keyboard.has_modifier(Modifier::Shift));
+        assert_eq!(keyboard.key_text(1, Key::KEY_Y), text("Z"));
+        press(&mut keyboard, 0, Key::KEY_LEFTSHIFT, 0);
+        assert!(!keyboard.has_modifier(Modifier::Shift));
+        assert_eq!(keyboard.key_text(1, Key::KEY_Y), text("z"));
+
+        // the locks are shared
+        press(&mut keyboard, 0, Key::KEY_CAPSLOCK, 1);
+        press(&mut keyboard, 0, Key::KEY_CAPSLOCK, 0);
+        assert_eq!(keyboard.key_text(1, Key::KEY_Y), text("Z"));
+        keyboard.add_device(2, &xkb::test_keymap("us"));
+        assert_eq!(keyboard.key_text(2, Key::KEY_Y), text("Y"));
+    }
+
+    #[test]
+    fn test_keyboard_dead_keys() {
+        let path = std::env::temp_dir().join(format!("shiv-test-compose-{}", std::process::id()));
+        std::fs::write(&path, "<dead_acute> <e> : \"é\" eacute\n").unwrap();
+        let table = compose::Table::from_file(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut keyboard = keyboard_with("de", Some(table));
+
+        // the acute dead key is left of backspace in the german layout
+        assert_eq!(keyboard.key_text(0, Key::KEY_EQUAL), KeyText::Composing);
+        assert_eq!(keyboard.key_text(0, Key::KEY_E), text("é"));
+        assert_eq!(keyboard.key_text(0, Key::KEY_E), text("e"));
+        assert_eq!(keyboard.key_text(0, Key::KEY_EQUAL), KeyText::Composing);
+        assert_eq!(keyboard.key_text(0, Key::KEY_X), KeyText::Composing);
+        assert_eq!(keyboard.key_text(0, Key::KEY_LEFT), KeyText::None);
+
+        // the repeats of a held dead key don't end the sequence
+        assert_eq!(
+            keyboard.event_text(0, Key::KEY_EQUAL, 1),
+            KeyText::Composing
+        );
+        assert_eq!(keyboard.event_text(0, Key::KEY_EQUAL, 2), KeyText::None);
+        assert_eq!(keyboard.event_text(0, Key::KEY_EQUAL, 2), KeyText::None);
+        assert_eq!(keyboard.event_text(0, Key::KEY_EQUAL, 0), KeyText::None);
+        assert_eq!(keyboard.event_text(0, Key::KEY_E, 1), text("é"));
+
+        // the key which completed the sequence repeats its own character
+        assert_eq!(keyboard.key_text(0, Key::KEY_EQUAL), KeyText::Composing);
+        assert_eq!(keyboard.plain_text(0, Key::KEY_E), text("e"));
+        assert_eq!(keyboard.key_text(0, Key::KEY_E), text("é"));
+        assert_eq!(keyboard.plain_text(0, Key::KEY_LEFT), KeyText::None);
+
+        // the dead keys without a sequence type their accent
+        let spacing = |text: &str| KeyText::Spacing(text.to_string());
+        assert_eq!(keyboard.key_text(0, Key::KEY_GRAVE), spacing("^"));
+        assert_eq!(keyboard.key_text(0, Key::KEY_E), text("e"));
+        let mut keyboard = keyboard_with("de", None);
+        assert_eq!(keyboard.key_text(0, Key::KEY_EQUAL), spacing("'"));
+        assert_eq!(keyboard.key_text(0, Key::KEY_E), text("e"));
     }
 }
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+
+/// Maximum number of killed texts to keep.
+const MAX_ENTRIES: usize = 32;
+
+#[derive(Debug, Default, Clone)]
+/// The texts killed from the entry, to be yanked back like in readline.
+pub struct KillRing {
+    /// The killed texts, the most recent first.
+    entries: VecDeque<String>,
+    /// The index of the last yanked entry.
+    yanked: usize,
+}
+
+impl KillRing {
+    /// Add a killed text to the ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The killed text.
+    pub fn push(&mut self, text: String) {
+        self.entries.push_front(text);
+        self.entries.truncate(MAX_ENTRIES);
+    }
+
+    /// Add a killed text to the most recent entry, for consecutive kills.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The killed text.
+    /// * `before` - Whether the text was killed before the previous one, e.g. by a backward kill.
+    pub fn append(&mut self, text: String, before: bool) {
+        match self.entries.front_mut() {
+            Some(entry) if before => entry.insert_str(0, &text),
+            Some(entry) => entry.push_str(&text),
+            None => self.push(text),
+        }
+    }
+
+    /// Get the most recent entry.
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yanked = 0;
+        self.entries.front().map(String::as_str)
+    }
+
+    /// Get the entry before the last yanked one, wrapping around.
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.yanked = (self.yanked + 1) % self.entries.len();
+        self.entries.get(self.yanked).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kill_ring() {
+        let mut ring = KillRing::default();
+        assert_eq!(ring.yank(), None);
+        assert_eq!(ring.yank_pop(), None);
+
+        ring.push("foo".to_string());
+        ring.append(" bar".to_string(), false);
+        ring.append("baz ".to_string(), true);
+        ring.push("qux".to_string());
+        assert_eq!(ring.yank(), Some("qux"));
+        assert_eq!(ring.yank_pop(), Some("baz foo bar"));
+        assert_eq!(ring.yank_pop(), Some("qux"));
+        assert_eq!(ring.yank(), Some("qux"));
+
+        for i in 0..MAX_ENTRIES {
+            ring.push(i.to_string());
+        }
+        assert_eq!(ring.entries.len(), MAX_ENTRIES);
+        assert_eq!(ring.entries.back().map(String::as_str), Some("0"));
+    }
+}
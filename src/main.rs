@@ -3,31 +3,86 @@ use evdev::{Device, EventStream};
 use tokio::{
     spawn,
     sync::oneshot::{channel, Sender},
+    task::{spawn_blocking, JoinHandle},
+    time::{sleep_until, Instant},
 };
 use tokio_stream::{StreamExt, StreamMap};
 
 use std::error::Error;
 
+mod bindings;
 mod cli;
+mod complete;
+mod compose;
+mod config;
+mod entry;
+mod history;
 mod keyboard;
+mod kill_ring;
 mod permissions;
+mod repeat;
 mod terminal;
 mod uinput;
+mod undo;
 mod utils;
+mod vi;
+mod xkb;
 
 async fn handle_events(
     uid: u32,
+    caps_lock_key: Option<evdev::Key>,
     mut keyboard: keyboard::Keyboard,
     mut terminal: terminal::Terminal,
+    mut repeater: repeat::Repeater,
     mut stream_map: StreamMap<usize, EventStream>,
 ) -> Result<(), Box<dyn Error>> {
     // When a command is running, this will be set to Some.
     let mut abort_signal: Option<Sender<()>> = None;
+    // The completion whose candidates are being listed, the keys are handled meanwhile.
+    let mut completion: Option<JoinHandle<(terminal::CompletionRequest, Vec<String>)>> = None;
 
     log::info!("Listening for keyboard events...");
-    log::info!("Ctrl-C/ESC to exit");
+    log::info!("Ctrl-C/ESC to exit, unless rebound");
     // Event loop
-    while let Some((_, Ok(event))) = stream_map.next().await {
+    loop {
+        if let Some(request) = terminal.take_completion_request() {
+            // a previous completion still running is superseded, its candidates are dropped
+            completion = Some(spawn_blocking(move || {
+                let candidates = request.candidates();
+                (request, candidates)
+            }));
+        }
+        let next_repeat = repeater.next();
+        let repeat_at = Instant::from_std(next_repeat.unwrap_or_else(std::time::Instant::now));
+        let (device, event) = tokio::select! {
+            event = stream_map.next() => match event {
+                Some((device, Ok(event))) => (device, event),
+                _ => break,
+            },
+            _ = sleep_until(repeat_at), if next_repeat.is_some() => {
+                if let Some((device, key)) = repeater.tick(std::time::Instant::now()) {
+                    log::trace!("Repeat: {:?}", key);
+                    // the app repeats the key's own character with the current modifiers, even if
+                    // the press completed a sequence or shift was released since
+                    let text = keyboard.plain_text(device, key);
+                    if let terminal::EventFlag::Emit =
+                        terminal.handle_key(key, &text, keyboard.modifiers())?
+                    {
+                        terminal.send_key(key, keyboard.modifiers())?
+                    }
+                }
+                continue;
+            }
+            result = async { completion.as_mut().expect("checked by the precondition").await },
+                if completion.is_some() => {
+                completion = None;
+                match result {
+                    Ok((request, candidates)) => terminal.finish_completion(&request, candidates)?,
+                    Err(e) => log::warn!("Failed to complete: {}", e),
+                }
+                continue;
+            }
+        };
         // Event is passed to the keyboard class.
         // It is then passed to the terminal class.
         // The keyboard class keeps track of the state of the keyboard.
@@ -37,38 +92,55 @@ async fn handle_events(
         log::trace!("Keyboard state: {:?}", keyboard);
         match event.kind() {
             evdev::InputEventKind::Key(key) => {
-                keyboard.handle_event(event, key);
+                // decode the key press before it updates the keyboard state
+                let text = keyboard.event_text(device, key, event.value());
+                keyboard.handle_event(device, event, key);
+                let action = if event.value() == 1 {
+                    terminal.action(key, keyboard.modifiers())
+                } else {
+                    None
+                };
                 if event.value() == 0 {
+                    repeater.release(key);
                     // Re-emit all key releases
                     terminal.emit(&[event]).unwrap_or_else(|e| {
                         log::error!("Failed to emit key: {}", e);
                     });
-                } else if abort_signal.is_none() {
+                } else if event.value() == 1 && abort_signal.is_none() {
                     // don't update the terminal state if cmd is running
                     // Re-emit key presses based on the terminal state and capabilities
-                    match terminal.handle_key(key, keyboard.is_shift())? {
+                    repeater.stop();
+                    terminal.set_caps_lock(
+                        caps_lock_key.filter(|_| keyboard.is_locked(xkb::Lock::Caps)),
+                    );
+                    match terminal.handle_key(key, &text, keyboard.modifiers())? {
                         terminal::EventFlag::Emit => {
                             log::debug!("Passing through {:?}", event);
-                            // here we emit the event as a single key press regardless of if it was a held down
-                            // key or not. This is because we are not handling key repeats. And allows the
-                            // grabbed keyboard to decide the rates of the virtual device.
-                            terminal.send_key(key, keyboard.is_shift())?
+                            // here we emit the event as a single key press, the kernel's repeats are
+                            // ignored and the held key is repeated by the `repeater` instead, so the
+                            // app doesn't repeat keys the entry didn't record.
+                            terminal.send_key(key, keyboard.modifiers())?;
                         }
                         terminal::EventFlag::Block => {}
                     }
+                    if keyboard.key_repeats(device, key) && text != keyboard::KeyText::Composing {
+                        repeater.press(device, key, std::time::Instant::now());
+                    }
                 }
 
-                if keyboard.is_ctrl_c() || keyboard.is_escape() {
-                    log::info!("Ctrl-C/ESC detected, exiting...");
+                if action == Some(bindings::Action::Cancel) {
+                    log::info!("Cancel detected, exiting...");
                     if let Some(signal) = abort_signal {
                         log::info!("Killing running command");
                         signal.send(()).map_err(|_| "Failed to send abort signal")?;
                     }
                     terminal.clear()?;
                     break;
-                } else if keyboard.is_enter() && abort_signal.is_none() {
-                    permissions::drop_privileges(uid)?;
-                    log::debug!("Dropped privileges");
+                } else if action == Some(bindings::Action::Submit) && abort_signal.is_none() {
+                    repeater.stop();
+                    terminal.add_to_history().unwrap_or_else(|e| {
+                        log::warn!("Failed to save the history: {}", e);
+                    });
                     let runner = terminal.clone();
                     let (send, recv) = channel::<()>();
                     abort_signal = Some(send);
@@ -101,6 +173,7 @@ async fn handle_events(
                     });
                 }
             }
+            evdev::InputEventKind::Led(led) => keyboard.set_led(led, event.value() != 0),
             evdev::InputEventKind::Synchronization(_) => terminal.emit(&[event])?,
             _ => {}
         }
@@ -141,13 +214,66 @@ async fn _main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // the lock states, read before the devices are turned into streams
+    let leds = keyboard_devices
+        .iter()
+        .map(Device::get_led_state)
+        .collect::<Result<Vec<_>, _>>()?;
+
     let mut stream_map = StreamMap::new();
+    let mut device_names = Vec::new();
     // Grab the keyboards and feed their streams into `stream_map`.
     for (i, mut device) in keyboard_devices.into_iter().enumerate() {
+        device_names.push(device.name().unwrap_or_default().to_string());
         device.grab()?;
         stream_map.insert(i, device.into_event_stream()?);
     }
-    let config = terminal::TerminalConfig {
+    // the devices are open, nothing else needs root: the user's files are read as the user
+    permissions::drop_privileges(uid)?;
+    log::debug!("Dropped privileges");
+
+    let config = config::Config::load(args.config.as_deref(), uid)?;
+    log::debug!("config: {:?}", config);
+
+    let profile = config.profile(args.profile.as_deref())?;
+    log::debug!("profile: {:?}", profile);
+    let editing_mode = if args.vi {
+        vi::EditingMode::Vi
+    } else {
+        profile.editing_mode.unwrap_or_default()
+    };
+    let mut bindings = bindings::Bindings::new(editing_mode);
+    bindings.extend(&profile.bindings);
+    if let Some(policy) = profile.shortcuts {
+        bindings.set_shortcuts(policy);
+    }
+
+    let rule_names = config.keyboard.clone().resolve();
+    log::info!("Keyboard layout: {:?}", rule_names);
+    let keymap = xkb::Keymap::from_names(&rule_names)?;
+
+    let compose = compose::Table::load(utils::get_home_dir(uid)?.as_deref()).unwrap_or_else(|e| {
+        log::warn!("Failed to load the Compose table: {}", e);
+        None
+    });
+    let mut keyboard = keyboard::Keyboard::new(compose);
+    for (i, name) in device_names.iter().enumerate() {
+        match config.devices.get(name) {
+            Some(names) => {
+                let names = names.clone().or(rule_names.clone());
+                log::info!("Keyboard layout of {:?}: {:?}", name, names);
+                keyboard.add_device(i, &xkb::Keymap::from_names(&names)?);
+            }
+            None => keyboard.add_device(i, &keymap),
+        }
+    }
+    let completer = complete::completer(
+        &profile.completer.clone().unwrap_or_default(),
+        uid,
+        utils::get_home_dir(uid)?,
+    )?;
+    let prompt = terminal::Prompt::default();
+    let terminal_config = terminal::TerminalConfig {
         pre_cmd,
         output_method: if args.type_output {
             terminal::OutputMethod::Type
@@ -155,18 +281,54 @@ async fn _main() -> Result<(), Box<dyn std::error::Error>> {
             terminal::OutputMethod::Paste
         },
         key_delay: args.key_delay,
+        unicode_fallback: args.unicode_fallback,
+        editing_mode,
+        bindings,
+        completer: Some(completer),
+        autosuggest: profile.autosuggest.unwrap_or(true),
+        prompt: terminal::Prompt {
+            prefix: profile.prompt_prefix.unwrap_or(prompt.prefix),
+            suffix: profile.prompt_suffix.unwrap_or(prompt.suffix),
+            normal_prefix: profile.normal_prompt_prefix.unwrap_or(prompt.normal_prefix),
+        },
     };
-    let keyboard = keyboard::Keyboard::new();
-    let terminal = terminal::Terminal::new(virt_device, config)?;
-    handle_events(uid, keyboard, terminal, stream_map).await?;
+    for led in [evdev::LedType::LED_CAPSL, evdev::LedType::LED_NUML] {
+        keyboard.set_led(led, leds.iter().any(|state| state.contains(led)));
+    }
+    let virt_keys = uinput::get_all_keys();
+    let char_map = keymap.char_map(&virt_keys);
+    let caps_lock_key = keymap.caps_lock_keys(&virt_keys).first().copied();
+    let spare_key = keymap.unmapped_keys(&virt_keys).first().copied();
+    log::debug!("Spare key: {:?}", spare_key);
+    let history = history::History::load(
+        config.history.clone(),
+        uid,
+        args.profile.as_deref(),
+        &args.pre_cmd,
+    )
+    .unwrap_or_else(|e| {
+        log::warn!("Failed to load the history: {}", e);
+        history::History::default()
+    });
+    // the prompt is typed right away, it must not be inverted by an active Caps Lock
+    let caps_lock = caps_lock_key.filter(|_| keyboard.is_locked(xkb::Lock::Caps));
+    let terminal = terminal::Terminal::new(
+        virt_device,
+        char_map,
+        spare_key,
+        history,
+        caps_lock,
+        terminal_config,
+    )?;
+    let repeater = repeat::Repeater::new(&config.repeat);
+    handle_events(uid, caps_lock_key, keyboard, terminal, repeater, stream_map).await?;
     Ok(())
 }
 
 #[tokio::main]
 async fn main() {
-    let status = _main().await;
-    utils::release_keyboards();
-    status.unwrap_or_else(|e| {
+    // the keyboards are ungrabbed as their devices are closed
+    _main().await.unwrap_or_else(|e| {
         log::error!("Failed to run: {}", e);
         std::process::exit(1);
     });
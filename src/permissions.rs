@@ -1,71 +1,422 @@
 use nix::unistd::{Gid, Uid, User};
-use std::env;
+use std::{
+    collections::HashMap, env, error::Error, ffi::CString, path::Path, process::Command,
+    sync::OnceLock,
+};
 
 // Inspired by https://github.com/waycrate/swhkd/blob/main/swhkd/src/perms.rs
 
-#[link(name = "c")]
-extern "C" {
-    fn geteuid() -> u32;
+/// The variables of the caller's session which the commands need, e.g. to reach the display.
+const SESSION_VARS: &[&str] = &[
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "XDG_RUNTIME_DIR",
+    "XDG_SESSION_TYPE",
+    "XDG_CURRENT_DESKTOP",
+    "XDG_SESSION_DESKTOP",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "TERM",
+    "TZ",
+    "LANG",
+    "LANGUAGE",
+];
+/// The `PATH` of the commands if the caller's environment has none.
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// The `PATH` of the caller's session, read by [`drop_privileges`] while it still can.
+static SESSION_PATH: OnceLock<String> = OnceLock::new();
+
+/// The accounts database, which the credentials are resolved from.
+trait Passwd {
+    /// Get the user with the `uid`.
+    fn user_by_uid(&self, uid: Uid) -> Result<Option<User>, Box<dyn Error>>;
+    /// Get the user named `name`.
+    fn user_by_name(&self, name: &str) -> Result<Option<User>, Box<dyn Error>>;
+    /// Get the groups the `user` is a member of.
+    fn groups(&self, user: &User) -> Result<Vec<Gid>, Box<dyn Error>>;
 }
 
-/// Get the UID of the caller.
-pub fn get_caller_uid() -> Result<u32, Box<dyn std::error::Error>> {
-    unsafe {
-        let mut uid = geteuid();
-        if uid == 0 {
-            if let Ok(sudo_uid) = env::var("SUDO_UID") {
-                uid = sudo_uid.parse::<u32>()?;
-            } else if let Ok(pkexec_uid) = env::var("PKEXEC_UID") {
-                uid = pkexec_uid.parse::<u32>()?;
-            } else {
-                return Err("UID is 0 but SUDO_UID and PKEXEC_UID are not set".into());
-            }
-        }
-        Ok(uid)
+/// The system's passwd and group databases.
+struct SystemPasswd;
+
+impl Passwd for SystemPasswd {
+    fn user_by_uid(&self, uid: Uid) -> Result<Option<User>, Box<dyn Error>> {
+        Ok(User::from_uid(uid)?)
     }
+
+    fn user_by_name(&self, name: &str) -> Result<Option<User>, Box<dyn Error>> {
+        Ok(User::from_name(name)?)
+    }
+
+    fn groups(&self, user: &User) -> Result<Vec<Gid>, Box<dyn Error>> {
+        Ok(nix::unistd::getgrouplist(
+            &CString::new(user.name.as_str())?,
+            user.gid,
+        )?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The identity of a user, which the process and the commands run with.
+struct Credentials {
+    user: User,
+    /// The supplementary groups, the primary group first.
+    groups: Vec<Gid>,
+}
+
+/// Resolve the UID of the caller, from the variables the elevating tool sets if running as root.
+///
+/// # Arguments
+///
+/// * `euid` - The effective UID of the process.
+/// * `var` - Get the value of an environment variable.
+/// * `passwd` - The [`Passwd`] database to look the `doas` user up in.
+fn resolve_caller_uid(
+    euid: Uid,
+    var: impl Fn(&str) -> Option<String>,
+    passwd: &impl Passwd,
+) -> Result<u32, Box<dyn Error>> {
+    if !euid.is_root() {
+        return Ok(euid.as_raw());
+    }
+    if let Some(uid) = var("SUDO_UID").or_else(|| var("PKEXEC_UID")) {
+        return Ok(uid.parse::<u32>()?);
+    }
+    match var("DOAS_USER") {
+        Some(name) => passwd
+            .user_by_name(&name)?
+            .map(|user| user.uid.as_raw())
+            .ok_or_else(|| format!("Unknown user: {}", name).into()),
+        None => Err("UID is 0 but SUDO_UID, PKEXEC_UID and DOAS_USER are not set".into()),
+    }
+}
+
+/// Resolve the [`Credentials`] of the user with the `uid`.
+///
+/// # Arguments
+///
+/// * `uid` - The UID of the user.
+/// * `passwd` - The [`Passwd`] database.
+fn resolve_credentials(uid: u32, passwd: &impl Passwd) -> Result<Credentials, Box<dyn Error>> {
+    let user = passwd
+        .user_by_uid(Uid::from_raw(uid))?
+        .ok_or_else(|| format!("Unknown user: {}", uid))?;
+    let mut groups = passwd.groups(&user)?;
+    groups.retain(|gid| *gid != user.gid);
+    groups.insert(0, user.gid);
+    Ok(Credentials { user, groups })
+}
+
+/// Get the UID of the caller.
+pub fn get_caller_uid() -> Result<u32, Box<dyn Error>> {
+    resolve_caller_uid(Uid::effective(), |name| env::var(name).ok(), &SystemPasswd)
 }
 
-/// Drop privileges to the given user.
+/// Drop the privileges to the ones of the given user for good, once the devices are open.
+///
+/// The supplementary groups, and the real, effective and saved GID and UID are set to the user's.
 ///
 /// # Arguments
 ///
 /// * `user_uid` - The user to drop privileges to.
-pub fn drop_privileges(user_uid: u32) -> Result<(), Box<dyn std::error::Error>> {
-    let user_uid = Uid::from_raw(user_uid);
-    if let Some(user) = User::from_uid(user_uid)? {
-        set_initgroups(&user, user_uid.as_raw())?;
-        set_egid(user_uid.as_raw())?;
-        // set_euid(user_uid.as_raw())?;
-        Ok(())
-    } else {
-        Err("Failed to get user".into())
+///
+/// # Errors
+///
+/// This function will return an error if the privileges can't be dropped, or could be regained.
+pub fn drop_privileges(user_uid: u32) -> Result<(), Box<dyn Error>> {
+    let Credentials { user, groups } = resolve_credentials(user_uid, &SystemPasswd)?;
+    // the groups can't be changed without privileges, e.g. if shiv wasn't elevated
+    if Uid::effective().is_root() {
+        if let Some(path) = read_session_path() {
+            log::debug!("Session PATH: {}", path);
+            let _ = SESSION_PATH.set(path);
+        }
+        nix::unistd::setgroups(&groups)?;
     }
+    nix::unistd::setresgid(user.gid, user.gid, user.gid)?;
+    nix::unistd::setresuid(user.uid, user.uid, user.uid)?;
+    check_dropped(&user)
 }
 
-fn set_initgroups(user: &nix::unistd::User, gid: u32) -> Result<(), Box<dyn std::error::Error>> {
-    let gid = Gid::from_raw(gid);
-    nix::unistd::initgroups(&user.gecos, gid)?;
-    Ok(())
+/// Get the value of the variable `name` in the contents of a `/proc/<pid>/environ` file.
+///
+/// # Arguments
+///
+/// * `environ` - The `name=value` variables, separated by NUL bytes.
+/// * `name` - The name of the variable.
+fn environ_var(environ: &[u8], name: &str) -> Option<String> {
+    environ
+        .split(|byte| *byte == 0)
+        .find_map(|var| var.strip_prefix(name.as_bytes())?.strip_prefix(b"="))
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+}
+
+/// Read the `PATH` of the caller's session, from the environment of the parent process.
+///
+/// The elevating tools reset the `PATH`, e.g. to sudo's `secure_path`, which lacks the user's
+/// directories like `~/.local/bin`. The parent is either the tool, which kept the environment it
+/// was started with, or the process which started it. Reading its environment needs the
+/// privileges.
+fn read_session_path() -> Option<String> {
+    let path = format!("/proc/{}/environ", std::os::unix::process::parent_id());
+    let environ = std::fs::read(path).ok()?;
+    environ_var(&environ, "PATH").filter(|path| !path.is_empty())
 }
 
-fn set_egid(gid: u32) -> Result<(), Box<dyn std::error::Error>> {
-    let gid = Gid::from_raw(gid);
-    nix::unistd::setegid(gid)?;
+/// Check that the process runs as the `user` only, and can't switch back to root.
+fn check_dropped(user: &User) -> Result<(), Box<dyn Error>> {
+    let uids = nix::unistd::getresuid()?;
+    let gids = nix::unistd::getresgid()?;
+    if [uids.real, uids.effective, uids.saved] != [user.uid; 3]
+        || [gids.real, gids.effective, gids.saved] != [user.gid; 3]
+    {
+        return Err("Failed to drop the privileges".into());
+    }
+    let regained = (!user.uid.is_root() && nix::unistd::setuid(Uid::from_raw(0)).is_ok())
+        || (user.gid.as_raw() != 0 && nix::unistd::setgid(Gid::from_raw(0)).is_ok());
+    if regained {
+        return Err("The dropped privileges can be regained".into());
+    }
     Ok(())
 }
 
-// fn set_euid(uid: u32) -> Result<(), Box<dyn std::error::Error>> {
-//     let uid = Uid::from_raw(uid);
-//     nix::unistd::seteuid(uid)?;
-//     Ok(())
-// }
+/// Build the environment of the commands run as the `user`.
+///
+/// `HOME`, `USER`, `LOGNAME` and `SHELL` come from the user's passwd entry, `PATH`, the locale
+/// and the session variables from the caller's environment. The runtime directory and the session
+/// bus default to the user's ones, in case the elevating tool didn't keep them.
+///
+/// # Arguments
+///
+/// * `user` - The [`User`] the commands run as.
+/// * `vars` - The caller's environment variables.
+/// * `exists` - Check whether a path exists, e.g. the runtime directory.
+fn user_env(
+    user: &User,
+    vars: impl IntoIterator<Item = (String, String)>,
+    exists: impl Fn(&Path) -> bool,
+) -> HashMap<String, String> {
+    let mut env: HashMap<String, String> = vars
+        .into_iter()
+        .filter(|(name, _)| {
+            name == "PATH" || name.starts_with("LC_") || SESSION_VARS.contains(&name.as_str())
+        })
+        .collect();
+    env.entry("PATH".to_string())
+        .or_insert_with(|| DEFAULT_PATH.to_string());
+    let runtime_dir = Path::new("/run/user").join(user.uid.to_string());
+    if !env.contains_key("XDG_RUNTIME_DIR") && exists(&runtime_dir) {
+        env.insert(
+            "XDG_RUNTIME_DIR".to_string(),
+            runtime_dir.to_string_lossy().into_owned(),
+        );
+    }
+    if let Some(runtime_dir) = env.get("XDG_RUNTIME_DIR").map(Path::new) {
+        let bus = runtime_dir.join("bus");
+        if !env.contains_key("DBUS_SESSION_BUS_ADDRESS") && exists(&bus) {
+            let address = format!("unix:path={}", bus.to_string_lossy());
+            env.insert("DBUS_SESSION_BUS_ADDRESS".to_string(), address);
+        }
+    }
+    env.insert("HOME".to_string(), user.dir.to_string_lossy().into_owned());
+    env.insert("USER".to_string(), user.name.clone());
+    env.insert("LOGNAME".to_string(), user.name.clone());
+    env.insert(
+        "SHELL".to_string(),
+        user.shell.to_string_lossy().into_owned(),
+    );
+    env
+}
+
+/// Get the `PATH` the commands run with, the one of the caller's session if shiv was elevated.
+pub fn command_path() -> Option<String> {
+    SESSION_PATH
+        .get()
+        .cloned()
+        .or_else(|| env::var("PATH").ok())
+}
+
+/// Make the `command` run as the user, in their home directory and environment.
+///
+/// The command inherits the privileges of shiv, which must already be dropped to the user's with
+/// [`drop_privileges`]. Its `PATH` is the one of the caller's session, if shiv was elevated.
+///
+/// # Arguments
+///
+/// * `command` - The [`Command`] to set up.
+/// * `uid` - The UID of the user.
+///
+/// # Errors
+///
+/// This function will return an error if the user can't be found, or if the privileges aren't
+/// dropped to the user's.
+pub fn run_as_user(command: &mut Command, uid: u32) -> Result<(), Box<dyn Error>> {
+    let user = SystemPasswd
+        .user_by_uid(Uid::from_raw(uid))?
+        .ok_or_else(|| format!("Unknown user: {}", uid))?;
+    if Uid::effective() != user.uid || Uid::current() != user.uid {
+        return Err("The privileges must be dropped before running commands".into());
+    }
+    let vars = env::vars()
+        .filter(|(name, _)| name != "PATH")
+        .chain(command_path().map(|path| ("PATH".to_string(), path)));
+    command
+        .env_clear()
+        .envs(user_env(&user, vars, |path| path.exists()))
+        .current_dir(&user.dir);
+    Ok(())
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn user(name: &str, uid: u32, gid: u32) -> User {
+        User {
+            name: name.to_string(),
+            passwd: CString::new("x").unwrap(),
+            uid: Uid::from_raw(uid),
+            gid: Gid::from_raw(gid),
+            gecos: CString::new(format!("{} Doe", name)).unwrap(),
+            dir: format!("/home/{}", name).into(),
+            shell: "/bin/zsh".into(),
+        }
+    }
+
+    /// An in-memory [`Passwd`] database.
+    struct FakePasswd {
+        users: Vec<User>,
+        groups: HashMap<&'static str, Vec<u32>>,
+    }
+
+    impl Passwd for FakePasswd {
+        fn user_by_uid(&self, uid: Uid) -> Result<Option<User>, Box<dyn Error>> {
+            Ok(self.users.iter().find(|user| user.uid == uid).cloned())
+        }
+
+        fn user_by_name(&self, name: &str) -> Result<Option<User>, Box<dyn Error>> {
+            Ok(self.users.iter().find(|user| user.name == name).cloned())
+        }
+
+        fn groups(&self, user: &User) -> Result<Vec<Gid>, Box<dyn Error>> {
+            let groups = self.groups.get(user.name.as_str()).ok_or("No groups")?;
+            Ok(groups.iter().copied().map(Gid::from_raw).collect())
+        }
+    }
+
+    fn passwd() -> FakePasswd {
+        FakePasswd {
+            users: vec![
+                user("root", 0, 0),
+                user("alice", 1000, 100),
+                user("bob", 1001, 1001),
+            ],
+            groups: HashMap::from([("alice", vec![10, 100, 27]), ("bob", vec![])]),
+        }
+    }
+
     #[test]
     fn test_get_caller_uid() {
-        assert!(get_caller_uid().is_ok());
+        let passwd = passwd();
+        let vars = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let root = Uid::from_raw(0);
+        // not elevated, the variables are ignored
+        assert_eq!(
+            resolve_caller_uid(Uid::from_raw(1001), vars(&[("SUDO_UID", "1000")]), &passwd)
+                .unwrap(),
+            1001
+        );
+        assert_eq!(
+            resolve_caller_uid(root, vars(&[("SUDO_UID", "1000")]), &passwd).unwrap(),
+            1000
+        );
+        assert_eq!(
+            resolve_caller_uid(root, vars(&[("PKEXEC_UID", "1001")]), &passwd).unwrap(),
+            1001
+        );
+        assert_eq!(
+            resolve_caller_uid(root, vars(&[("DOAS_USER", "alice")]), &passwd).unwrap(),
+            1000
+        );
+        assert!(resolve_caller_uid(root, vars(&[("DOAS_USER", "carol")]), &passwd).is_err());
+        assert!(resolve_caller_uid(root, vars(&[("SUDO_UID", "alice")]), &passwd).is_err());
+        assert!(resolve_caller_uid(root, vars(&[]), &passwd).is_err());
+    }
+
+    #[test]
+    fn test_resolve_credentials() {
+        let passwd = passwd();
+        let credentials = resolve_credentials(1000, &passwd).unwrap();
+        // the login name, not the full name, and the primary group rather than the UID
+        assert_eq!(credentials.user.name, "alice");
+        assert_eq!(credentials.user.gid, Gid::from_raw(100));
+        assert_eq!(credentials.groups, [100, 10, 27].map(Gid::from_raw));
+
+        let credentials = resolve_credentials(1001, &passwd).unwrap();
+        assert_eq!(credentials.groups, [Gid::from_raw(1001)]);
+
+        assert!(resolve_credentials(4242, &passwd).is_err());
+    }
+
+    #[test]
+    fn test_environ_var() {
+        let environ = b"HOME=/home/alice\0PATH=/home/alice/.local/bin:/usr/bin\0PATHS=x\0EMPTY=\0";
+        assert_eq!(
+            environ_var(environ, "PATH").as_deref(),
+            Some("/home/alice/.local/bin:/usr/bin")
+        );
+        assert_eq!(environ_var(environ, "EMPTY").as_deref(), Some(""));
+        assert_eq!(environ_var(environ, "PAT"), None);
+        assert_eq!(environ_var(b"", "PATH"), None);
+    }
+
+    #[test]
+    fn test_user_env() {
+        let user = user("alice", 4242, 4242);
+        let vars = [
+            ("HOME", "/root"),
+            ("USER", "root"),
+            ("SUDO_COMMAND", "/usr/bin/shiv"),
+            ("WAYLAND_DISPLAY", "wayland-1"),
+            ("LC_TIME", "fr_FR.UTF-8"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let env = user_env(&user, vars.clone(), |_| false);
+        assert_eq!(env["HOME"], "/home/alice");
+        assert_eq!(env["USER"], "alice");
+        assert_eq!(env["LOGNAME"], "alice");
+        assert_eq!(env["SHELL"], "/bin/zsh");
+        assert_eq!(env["PATH"], DEFAULT_PATH);
+        assert_eq!(env["WAYLAND_DISPLAY"], "wayland-1");
+        assert_eq!(env["LC_TIME"], "fr_FR.UTF-8");
+        assert!(!env.contains_key("SUDO_COMMAND"));
+        assert!(!env.contains_key("XDG_RUNTIME_DIR"));
+        assert!(!env.contains_key("DBUS_SESSION_BUS_ADDRESS"));
+
+        // the user's runtime directory and session bus are filled in if they exist
+        let exists = |path: &Path| path.starts_with("/run/user/4242");
+        let env = user_env(&user, vars.clone(), exists);
+        assert_eq!(env["XDG_RUNTIME_DIR"], "/run/user/4242");
+        assert_eq!(
+            env["DBUS_SESSION_BUS_ADDRESS"],
+            "unix:path=/run/user/4242/bus"
+        );
+
+        // but the caller's are kept
+        let mut vars = vars.to_vec();
+        vars.push(("XDG_RUNTIME_DIR".to_string(), "/tmp/runtime".to_string()));
+        let env = user_env(&user, vars, exists);
+        assert_eq!(env["XDG_RUNTIME_DIR"], "/tmp/runtime");
+        assert!(!env.contains_key("DBUS_SESSION_BUS_ADDRESS"));
+
+        let env = user_env(&user, [("PATH".to_string(), "/bin".to_string())], |_| false);
+        assert_eq!(env["PATH"], "/bin");
     }
 }
@@ -0,0 +1,164 @@
+use evdev::Key;
+use serde::Deserialize;
+
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// The autorepeat settings of the held keys.
+pub struct RepeatConfig {
+    /// Time before a held key starts repeating, in ms.
+    pub delay: u64,
+    /// Number of repeats per second, 0 disables the autorepeat.
+    pub rate: u32,
+}
+
+impl Default for RepeatConfig {
+    fn default() -> Self {
+        Self {
+            delay: 600,
+            rate: 25,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// The key being repeated.
+struct HeldKey {
+    /// The index of the device the key is held on.
+    device: usize,
+    key: Key,
+    /// When the next repeat is due.
+    next: Instant,
+}
+
+#[derive(Debug)]
+/// Generates the repeats of the last pressed key while it is held.
+///
+/// The kernel's repeat events are ignored, so that every repeat goes through the
+/// [`crate::terminal::Terminal`] and the entry matches what was emitted. The text of each repeat
+/// is decoded when it is due, as the modifiers may have changed since the key was pressed.
+pub struct Repeater {
+    delay: Duration,
+    /// Time between repeats, `None` if the autorepeat is disabled.
+    interval: Option<Duration>,
+    held: Option<HeldKey>,
+}
+
+impl Repeater {
+    /// Create a new [`Repeater`].
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The [`RepeatConfig`] to use.
+    pub fn new(config: &RepeatConfig) -> Repeater {
+        Repeater {
+            delay: Duration::from_millis(config.delay),
+            interval: (config.rate > 0).then(|| Duration::from_secs(1) / config.rate),
+            held: None,
+        }
+    }
+
+    /// Start repeating the `key`, replacing the previously repeated key.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The index of the device the key was pressed on.
+    /// * `key` - The pressed [`Key`].
+    /// * `now` - When the key was pressed.
+    pub fn press(&mut self, device: usize, key: Key, now: Instant) {
+        self.held = self.interval.map(|_| HeldKey {
+            device,
+            key,
+            next: now + self.delay,
+        });
+    }
+
+    /// Stop repeating the `key` if it is the repeated one.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The released [`Key`].
+    pub fn release(&mut self, key: Key) {
+        if self.held.as_ref().is_some_and(|held| held.key == key) {
+            self.held = None;
+        }
+    }
+
+    /// Stop repeating.
+    pub fn stop(&mut self) {
+        self.held = None;
+    }
+
+    /// When the next repeat is due, if a key is repeating.
+    pub fn next(&self) -> Option<Instant> {
+        self.held.as_ref().map(|held| held.next)
+    }
+
+    /// Get the repeated key and its device if a repeat is due at `now`, and schedule the next one.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time.
+    pub fn tick(&mut self, now: Instant) -> Option<(usize, Key)> {
+        let interval = self.interval?;
+        let held = self.held.as_mut().filter(|held| held.next <= now)?;
+        held.next += interval;
+        // don't try to catch up if we fell behind
+        if held.next < now {
+            held.next = now + interval;
+        }
+        Some((held.device, held.key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_repeater() {
+        let mut repeater = Repeater::new(&RepeatConfig {
+            delay: 500,
+            rate: 20,
+        });
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        assert_eq!(repeater.next(), None);
+
+        repeater.press(0, Key::KEY_A, start);
+        assert_eq!(repeater.next(), Some(ms(500)));
+        assert_eq!(repeater.tick(ms(499)), None);
+        assert_eq!(repeater.tick(ms(500)), Some((0, Key::KEY_A)));
+        assert_eq!(repeater.next(), Some(ms(550)));
+        assert_eq!(repeater.tick(ms(520)), None);
+        assert_eq!(repeater.tick(ms(560)), Some((0, Key::KEY_A)));
+        assert_eq!(repeater.next(), Some(ms(600)));
+
+        // releasing another key doesn't stop the repeat
+        repeater.release(Key::KEY_B);
+        assert!(repeater.next().is_some());
+        repeater.release(Key::KEY_A);
+        assert_eq!(repeater.next(), None);
+        assert_eq!(repeater.tick(ms(1000)), None);
+
+        // the latest press replaces the repeated key
+        repeater.press(0, Key::KEY_A, start);
+        repeater.press(1, Key::KEY_B, ms(100));
+        assert_eq!(repeater.tick(ms(700)), Some((1, Key::KEY_B)));
+        // missed repeats are skipped
+        assert_eq!(repeater.next(), Some(ms(750)));
+        assert_eq!(repeater.tick(ms(2000)), Some((1, Key::KEY_B)));
+        assert_eq!(repeater.next(), Some(ms(2050)));
+        repeater.stop();
+        assert_eq!(repeater.next(), None);
+    }
+
+    #[test]
+    fn test_repeater_disabled() {
+        let mut repeater = Repeater::new(&RepeatConfig { delay: 0, rate: 0 });
+        repeater.press(0, Key::KEY_A, Instant::now());
+        assert_eq!(repeater.next(), None);
+        assert_eq!(repeater.tick(Instant::now()), None);
+    }
+}
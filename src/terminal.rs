@@ -1,178 +1,30 @@
 use evdev::{uinput::VirtualDevice, EventType, InputEvent, Key};
-use lazy_static::lazy_static;
 use tokio::process::Command;
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     error::Error,
     fmt::{Debug, Formatter},
     process::Stdio,
     sync::{Arc, Mutex},
 };
 
-extern crate arboard;
+use crate::{
+    bindings::{Action, Bindings},
+    complete::{split_word, Completer},
+    entry::{
+        blank_word_start, line_down, line_end, line_start, line_up, word_end, word_start, Entry,
+    },
+    history::{History, Search},
+    keyboard::{KeyText, Modifier},
+    kill_ring::KillRing,
+    permissions,
+    undo::{Edit, Snapshot, UndoStack},
+    vi::{self, EditingMode, Mode, Vi},
+    xkb::KeyCombo,
+};
 
-lazy_static! {
-    static ref KEY_TO_CHAR: HashMap<Key, char> = HashMap::from(
-        [
-            (Key::KEY_1, '1'),
-            (Key::KEY_2, '2'),
-            (Key::KEY_3, '3'),
-            (Key::KEY_4, '4'),
-            (Key::KEY_5, '5'),
-            (Key::KEY_6, '6'),
-            (Key::KEY_7, '7'),
-            (Key::KEY_8, '8'),
-            (Key::KEY_9, '9'),
-            (Key::KEY_0, '0'),
-            (Key::KEY_MINUS, '-'),
-            (Key::KEY_EQUAL, '='),
-            (Key::KEY_TAB, '\t'),
-            (Key::KEY_Q, 'q'),
-            (Key::KEY_W, 'w'),
-            (Key::KEY_E, 'e'),
-            (Key::KEY_R, 'r'),
-            (Key::KEY_T, 't'),
-            (Key::KEY_Y, 'y'),
-            (Key::KEY_U, 'u'),
-            (Key::KEY_I, 'i'),
-            (Key::KEY_O, 'o'),
-            (Key::KEY_P, 'p'),
-            (Key::KEY_LEFTBRACE, '['),
-            (Key::KEY_RIGHTBRACE, ']'),
-            (Key::KEY_A, 'a'),
-            (Key::KEY_S, 's'),
-            (Key::KEY_D, 'd'),
-            (Key::KEY_F, 'f'),
-            (Key::KEY_G, 'g'),
-            (Key::KEY_H, 'h'),
-            (Key::KEY_J, 'j'),
-            (Key::KEY_K, 'k'),
-            (Key::KEY_L, 'l'),
-            (Key::KEY_SEMICOLON, ';'),
-            (Key::KEY_APOSTROPHE, '\''),
-            (Key::KEY_GRAVE, '`'),
-            (Key::KEY_BACKSLASH, '\\'),
-            (Key::KEY_Z, 'z'),
-            (Key::KEY_X, 'x'),
-            (Key::KEY_C, 'c'),
-            (Key::KEY_V, 'v'),
-            (Key::KEY_B, 'b'),
-            (Key::KEY_N, 'n'),
-            (Key::KEY_M, 'm'),
-            (Key::KEY_COMMA, ','),
-            (Key::KEY_DOT, '.'),
-            (Key::KEY_SLASH, '/'),
-            (Key::KEY_SPACE, ' '),
-            // (Key::KEY_KPASTERISK, '*'),
-            // (Key::KEY_SPACE, ' '),
-            // (Key::KEY_KP7, '7'),
-            // (Key::KEY_KP8, '8'),
-            // (Key::KEY_KP9, '9'),
-            // (Key::KEY_KPMINUS, '-'),
-            // (Key::KEY_KP4, '4'),
-            // (Key::KEY_KP5, '5'),
-            // (Key::KEY_KP6, '6'),
-            // (Key::KEY_KPPLUS, '+'),
-            // (Key::KEY_KP1, '1'),
-            // (Key::KEY_KP2, '2'),
-            // (Key::KEY_KP3, '3'),
-            // (Key::KEY_KP0, '0'),
-            // (Key::KEY_KPDOT, '.'),
-        ]
-    );
-    static ref SHIFT_KEY_TO_CHAR: HashMap<Key, char> = HashMap::from(
-        [
-            (Key::KEY_1, '!'),
-            (Key::KEY_2, '@'),
-            (Key::KEY_3, '#'),
-            (Key::KEY_4, '$'),
-            (Key::KEY_5, '%'),
-            (Key::KEY_6, '^'),
-            (Key::KEY_7, '&'),
-            (Key::KEY_8, '*'),
-            (Key::KEY_9, '('),
-            (Key::KEY_0, ')'),
-            (Key::KEY_MINUS, '_'),
-            (Key::KEY_EQUAL, '+'),
-            (Key::KEY_TAB, '\t'),
-            (Key::KEY_Q, 'Q'),
-            (Key::KEY_W, 'W'),
-            (Key::KEY_E, 'E'),
-            (Key::KEY_R, 'R'),
-            (Key::KEY_T, 'T'),
-            (Key::KEY_Y, 'Y'),
-            (Key::KEY_U, 'U'),
-            (Key::KEY_I, 'I'),
-            (Key::KEY_O, 'O'),
-            (Key::KEY_P, 'P'),
-            (Key::KEY_LEFTBRACE, '{'),
-            (Key::KEY_RIGHTBRACE, '}'),
-            (Key::KEY_A, 'A'),
-            (Key::KEY_S, 'S'),
-            (Key::KEY_D, 'D'),
-            (Key::KEY_F, 'F'),
-            (Key::KEY_G, 'G'),
-            (Key::KEY_H, 'H'),
-            (Key::KEY_J, 'J'),
-            (Key::KEY_K, 'K'),
-            (Key::KEY_L, 'L'),
-            (Key::KEY_SEMICOLON, ':'),
-            (Key::KEY_APOSTROPHE, '"'),
-            (Key::KEY_GRAVE, '~'),
-            (Key::KEY_BACKSLASH, '|'),
-            (Key::KEY_Z, 'Z'),
-            (Key::KEY_X, 'X'),
-            (Key::KEY_C, 'C'),
-            (Key::KEY_V, 'V'),
-            (Key::KEY_B, 'B'),
-            (Key::KEY_N, 'N'),
-            (Key::KEY_M, 'M'),
-            (Key::KEY_COMMA, '<'),
-            (Key::KEY_DOT, '>'),
-            (Key::KEY_SLASH, '?'),
-            (Key::KEY_SPACE, ' '),
-            // (Key::KEY_KPASTERISK, '*'),
-            // (Key::KEY_SPACE, ' '),
-            // (Key::KEY_KP7, '7'),
-            // (Key::KEY_KP8, '8'),
-            // (Key::KEY_KP9, '9'),
-            // (Key::KEY_KPMINUS, '-'),
-            // (Key::KEY_KP4, '4'),
-            // (Key::KEY_KP5, '5'),
-            // (Key::KEY_KP6, '6'),
-            // (Key::KEY_KPPLUS, '+'),
-            // (Key::KEY_KP1, '1'),
-            // (Key::KEY_KP2, '2'),
-            // (Key::KEY_KP3, '3'),
-            // (Key::KEY_KP0, '0'),
-            // (Key::KEY_KPDOT, '.'),
-        ]
-    );
-
-    pub static ref HANDLED_KEYS: HashSet<Key> = HashSet::from_iter(
-        KEY_TO_CHAR.iter().map(|(k, _)| *k).chain(
-            [
-                Key::KEY_BACKSPACE,
-                Key::KEY_LEFT,
-                Key::KEY_RIGHT,
-                Key::KEY_DELETE
-            ]
-        )
-    );
-
-    // This map could maybe use phf construct it at compile time
-    pub static ref CHAR_TO_KEY: HashMap<char, (Key, bool)> = HashMap::from_iter(
-        KEY_TO_CHAR.iter().map(|(k, v)| (*v, (*k, false)))
-            .chain(SHIFT_KEY_TO_CHAR.iter().map(|(k, v)| (*v, (*k, true))))
-            .chain(
-                [
-                    ('\n', (Key::KEY_ENTER, false)),
-                    ('\t', (Key::KEY_TAB, false))
-                ]
-            )
-    );
-}
+extern crate arboard;
 
 /// Used to signal if the event should be blocked or passed through to the virtual device.
 pub enum EventFlag {
@@ -188,6 +40,38 @@ pub enum OutputMethod {
     Type,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+/// How to type the characters which no key of the layout produces.
+pub enum UnicodeFallback {
+    /// Enter the code point in hexadecimal after Ctrl+Shift+U, as supported by GTK and IBus.
+    CtrlShiftU,
+    /// Temporarily map a spare keycode to the character with `xmodmap`, X11 only.
+    Remap,
+    /// Paste the characters through the clipboard.
+    Paste,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The markers typed around the entry, which show where it is in the app.
+pub struct Prompt {
+    /// Typed before the entry.
+    pub prefix: String,
+    /// Typed after the entry.
+    pub suffix: String,
+    /// Typed before the entry instead of the prefix in the vi normal mode.
+    pub normal_prefix: String,
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Self {
+            prefix: ">".to_string(),
+            suffix: "<".to_string(),
+            normal_prefix: ":".to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Control the [`Terminal`]'s behavior.
 pub struct TerminalConfig {
@@ -195,6 +79,18 @@ pub struct TerminalConfig {
     pub pre_cmd: Vec<String>,
     pub output_method: OutputMethod,
     pub key_delay: Option<std::time::Duration>,
+    /// How to type the characters which aren't in the layout.
+    pub unicode_fallback: UnicodeFallback,
+    /// How the entry is edited.
+    pub editing_mode: EditingMode,
+    /// The actions bound to the keys.
+    pub bindings: Bindings,
+    /// Provides the Tab completions, if any.
+    pub completer: Option<Arc<dyn Completer>>,
+    /// Whether to suggest the rest of the entry from the history.
+    pub autosuggest: bool,
+    /// The markers around the entry.
+    pub prompt: Prompt,
 }
 
 impl Default for TerminalConfig {
@@ -203,18 +99,116 @@ impl Default for TerminalConfig {
             pre_cmd: vec!["bash".to_string(), "-c".to_string()],
             output_method: OutputMethod::Paste,
             key_delay: None,
+            unicode_fallback: UnicodeFallback::Paste,
+            editing_mode: EditingMode::default(),
+            bindings: Bindings::default(),
+            completer: None,
+            autosuggest: true,
+            prompt: Prompt::default(),
         }
     }
 }
 
+/// Time for the app to read the clipboard, before it is replaced by the next pasted segment.
+const PASTE_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+/// Time for a remapped key press to be processed, before the mapping is restored.
+const REMAP_DELAY: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Run `xmodmap` with the `expression`.
+fn xmodmap(expression: &str) -> Result<(), Box<dyn Error>> {
+    let status = std::process::Command::new("xmodmap")
+        .args(["-e", expression])
+        .status()
+        .map_err(|e| format!("Failed to run xmodmap: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("xmodmap {:?} failed: {}", expression, status).into())
+    }
+}
+
+/// Find the lengths of the common prefix and suffix of `old` and `new`, which don't overlap.
+fn common_affixes<T: PartialEq>(old: &[T], new: &[T]) -> (usize, usize) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    (prefix, suffix)
+}
+
+#[derive(Debug, Clone)]
+/// The candidates of the last completion, cycled through by repeated completions.
+struct Completion {
+    candidates: Vec<String>,
+    /// The index of the inserted candidate.
+    index: usize,
+    /// The start of the completed word in the entry.
+    start: usize,
+}
+
+#[derive(Debug, Clone)]
+/// A completion of the word before the cursor, whose candidates are listed in the background as
+/// the completer may run commands.
+pub struct CompletionRequest {
+    /// Tells the requests apart, only the candidates of the last one are inserted.
+    id: u64,
+    /// The entry up to the cursor.
+    line: String,
+    completer: Arc<dyn Completer>,
+}
+
+impl CompletionRequest {
+    /// List the candidates, which blocks until the completer answers.
+    pub fn candidates(&self) -> Vec<String> {
+        self.completer.complete(&self.line).unwrap_or_else(|e| {
+            log::warn!("Failed to complete: {}", e);
+            Vec::new()
+        })
+    }
+}
+
 #[derive(Clone)]
 /// Represents the emulated terminal the user is typing into.
 /// It keeps track of their inputs, controls the flow of events to the virtual device, constructs
 /// the command string, runs it and types back the output.
 pub struct Terminal {
-    entry: Vec<char>,
+    entry: Entry,
     pos: usize,
+    kill_ring: KillRing,
+    /// The last action performed, to chain kills and yanks.
+    last_action: Option<Action>,
+    /// The range of the entry inserted by the last yank.
+    yanked: Option<(usize, usize)>,
+    /// The state of the vi editing mode, if enabled.
+    vi: Option<Vi>,
+    /// The undo and redo history of the entry.
+    undo: UndoStack,
+    /// The previously submitted entries.
+    history: History,
+    /// The ongoing history search, with the entry to restore if it is aborted.
+    search: Option<(Search, Snapshot)>,
+    /// The last completion, while its candidates are cycled through.
+    completion: Option<Completion>,
+    /// The completion to list the candidates of, until the caller takes it.
+    completion_request: Option<CompletionRequest>,
+    /// The ID of the completion waiting for its candidates, until another key is handled.
+    pending_completion: Option<u64>,
+    /// The number of requested completions, which the next request is identified by.
+    completions: u64,
+    /// The suggested suffix typed and selected after the entry, which isn't part of it.
+    ghost: String,
+    /// The end of the selected text opposite to the cursor, while text is selected.
+    anchor: Option<usize>,
     device: Arc<Mutex<VirtualDevice>>,
+    char_map: Arc<HashMap<char, KeyCombo>>,
+    /// A key the layout doesn't use, for the [`UnicodeFallback::Remap`] fallback.
+    spare_key: Option<Key>,
+    /// The key to toggle Caps Lock with when it is active, as it inverts the case of the typed
+    /// letters.
+    caps_lock: Option<Key>,
     config: TerminalConfig,
 }
 
@@ -234,19 +228,47 @@ impl Terminal {
     /// # Arguments
     ///
     /// * `device` - The [`VirtualDevice`] to use for sending events.
+    /// * `char_map` - The [`KeyCombo`] to type each character with, in the user's layout.
+    /// * `spare_key` - A key the layout doesn't use, which can be remapped to type any character.
+    /// * `history` - The [`History`] of the previously submitted entries.
+    /// * `caps_lock` - The key which toggles Caps Lock if it is active, see
+    ///   [`Terminal::set_caps_lock`]. It is needed to type the prompt.
     /// * `config` - The [`TerminalConfig`] to use.
     ///
     /// # Errors
     ///
     /// This function will return an error if the [`Terminal::init`] call fails.
-    pub fn new(device: VirtualDevice, config: TerminalConfig) -> Result<Terminal, Box<dyn Error>> {
+    pub fn new(
+        device: VirtualDevice,
+        char_map: HashMap<char, KeyCombo>,
+        spare_key: Option<Key>,
+        history: History,
+        caps_lock: Option<Key>,
+        config: TerminalConfig,
+    ) -> Result<Terminal, Box<dyn Error>> {
         let term = Terminal {
-            entry: Vec::new(),
+            entry: Entry::default(),
             pos: 0,
+            kill_ring: KillRing::default(),
+            last_action: None,
+            yanked: None,
+            vi: (config.editing_mode == EditingMode::Vi).then(Vi::default),
+            undo: UndoStack::default(),
+            history,
+            search: None,
+            completion: None,
+            completion_request: None,
+            pending_completion: None,
+            completions: 0,
+            ghost: String::new(),
+            anchor: None,
             device: Arc::new(Mutex::new(device)),
+            char_map: Arc::new(char_map),
+            spare_key,
+            caps_lock,
             config,
         };
-        // Write the >< chars
+        // Write the prompt markers
         term.init()?;
         Ok(term)
     }
@@ -256,143 +278,984 @@ impl Terminal {
     /// # Arguments
     ///
     /// * `key` - The [`Key`] to send.
+    /// * `modifiers` - The held modifier keys, which are sent along with the key.
     ///
     /// # Errors
     ///
     /// This function will return an error if the event sending fails.
-    pub fn send_key(&self, key: Key, shift: bool) -> Result<(), Box<dyn Error>> {
-        let events = self.key_events(key, shift);
+    pub fn send_key(
+        &self,
+        key: Key,
+        modifiers: &HashMap<Key, Modifier>,
+    ) -> Result<(), Box<dyn Error>> {
+        let modifiers: Vec<Key> = modifiers.keys().copied().collect();
+        let events = self.key_events(key, &modifiers);
         self.send_events(events)
     }
 
-    fn key_events(&self, key: Key, shift: bool) -> Vec<InputEvent> {
-        if shift {
-            vec![
-                InputEvent::new(EventType::KEY, Key::KEY_LEFTSHIFT.code(), 1),
-                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                InputEvent::new(EventType::KEY, key.code(), 1),
-                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                InputEvent::new(EventType::KEY, key.code(), 0),
-                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                InputEvent::new(EventType::KEY, Key::KEY_LEFTSHIFT.code(), 0),
-                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-            ]
-        } else {
-            vec![
-                InputEvent::new(EventType::KEY, key.code(), 1),
-                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-                InputEvent::new(EventType::KEY, key.code(), 0),
-                InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
-            ]
+    /// Set whether Caps Lock is active, it is toggled off while typing out the command output.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key which toggles Caps Lock if it is active, `None` otherwise.
+    pub fn set_caps_lock(&mut self, key: Option<Key>) {
+        self.caps_lock = key;
+    }
+
+    /// Generate the events to press `key` while holding the `modifiers`.
+    fn key_events(&self, key: Key, modifiers: &[Key]) -> Vec<InputEvent> {
+        let mut events = Vec::with_capacity(4 * (modifiers.len() + 1));
+        for modifier in modifiers {
+            events.push(InputEvent::new(EventType::KEY, modifier.code(), 1));
+            events.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+        }
+        events.extend_from_slice(&[
+            InputEvent::new(EventType::KEY, key.code(), 1),
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+            InputEvent::new(EventType::KEY, key.code(), 0),
+            InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+        ]);
+        for modifier in modifiers.iter().rev() {
+            events.push(InputEvent::new(EventType::KEY, modifier.code(), 0));
+            events.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
         }
+        events
+    }
+
+    /// Generate the events to type `c` in the user's layout.
+    fn char_events(&self, c: char) -> Option<Vec<InputEvent>> {
+        self.char_map
+            .get(&c)
+            .map(|combo| self.key_events(combo.key, &combo.modifiers))
     }
 
     fn init(&self) -> Result<(), Box<dyn Error>> {
-        // Send the >< chars and move the cursor to the middle
-        self.send_key(Key::KEY_DOT, true)?;
-        self.send_key(Key::KEY_COMMA, true)?;
-        self.send_key(Key::KEY_LEFT, false)?;
-        Ok(())
+        // Type the markers and move the cursor between them
+        let prompt = &self.config.prompt;
+        self.type_text(&format!("{}{}", self.prefix(), prompt.suffix))?;
+        let n_lefts = Entry::new(&prompt.suffix).len();
+        self.send_events(self.key_events(Key::KEY_LEFT, &[]).repeat(n_lefts))
     }
 
-    fn backspace(&mut self) -> EventFlag {
+    /// The marker currently typed before the entry, which depends on the vi mode.
+    fn prefix(&self) -> &str {
+        match self.vi.as_ref().map(Vi::mode) {
+            Some(Mode::Normal) => &self.config.prompt.normal_prefix,
+            _ => &self.config.prompt.prefix,
+        }
+    }
+
+    /// Press and release `key`.
+    fn tap(&self, key: Key) -> Result<(), Box<dyn Error>> {
+        self.send_events(self.key_events(key, &[]))
+    }
+
+    fn backspace(&mut self) -> Result<EventFlag, Box<dyn Error>> {
         if self.pos > 0 {
             self.pos -= 1;
-            self.entry.remove(self.pos);
-            return EventFlag::Emit;
+            self.entry.remove(self.pos..self.pos + 1);
+            self.tap(Key::KEY_BACKSPACE)?;
         }
-        EventFlag::Block
+        Ok(EventFlag::Block)
     }
 
-    fn delete(&mut self) -> EventFlag {
+    fn delete(&mut self) -> Result<EventFlag, Box<dyn Error>> {
         if self.pos < self.entry.len() {
-            self.entry.remove(self.pos);
-            return EventFlag::Emit;
+            self.entry.remove(self.pos..self.pos + 1);
+            self.tap(Key::KEY_DELETE)?;
         }
-        EventFlag::Block
+        Ok(EventFlag::Block)
     }
 
-    fn left(&mut self) -> EventFlag {
+    fn left(&mut self) -> Result<EventFlag, Box<dyn Error>> {
         if self.pos > 0 {
             self.pos -= 1;
-            return EventFlag::Emit;
+            self.tap(Key::KEY_LEFT)?;
         }
-        EventFlag::Block
+        Ok(EventFlag::Block)
     }
 
-    fn right(&mut self) -> EventFlag {
+    fn right(&mut self) -> Result<EventFlag, Box<dyn Error>> {
         if self.pos < self.entry.len() {
             self.pos += 1;
-            return EventFlag::Emit;
+            self.tap(Key::KEY_RIGHT)?;
         }
-        EventFlag::Block
+        Ok(EventFlag::Block)
     }
 
-    fn home(&mut self) -> Result<EventFlag, Box<dyn Error>> {
-        if self.pos > 0 {
-            let n_lefts = self.pos;
-            let left_events = self.key_events(Key::KEY_LEFT, false).repeat(n_lefts);
-            log::trace!("home left events: {:?}", left_events);
-            self.send_events(left_events)?;
-            self.pos = 0;
+    /// The range of the selected text, if any.
+    fn selection(&self) -> Option<(usize, usize)> {
+        self.anchor
+            .map(|anchor| (anchor.min(self.pos), anchor.max(self.pos)))
+    }
+
+    /// Extend the selection from the cursor to `pos`, with Shift+arrow presses like the apps
+    /// select text.
+    fn select_to(&mut self, pos: usize) -> Result<EventFlag, Box<dyn Error>> {
+        let anchor = *self.anchor.get_or_insert(self.pos);
+        let events = if pos < self.pos {
+            self.key_events(Key::KEY_LEFT, &[Key::KEY_LEFTSHIFT])
+                .repeat(self.pos - pos)
+        } else {
+            self.key_events(Key::KEY_RIGHT, &[Key::KEY_LEFTSHIFT])
+                .repeat(pos - self.pos)
+        };
+        log::trace!("Select events: {:?}", events);
+        self.send_events(events)?;
+        self.pos = pos;
+        if pos == anchor {
+            self.anchor = None;
+        }
+        Ok(EventFlag::Block)
+    }
+
+    /// Events to unselect the text, leaving the cursor where it is.
+    fn unselect_events(&self) -> Vec<InputEvent> {
+        match self.anchor {
+            // the arrows move the cursor to the matching end of the selection
+            Some(anchor) if anchor > self.pos => self.key_events(Key::KEY_LEFT, &[]),
+            Some(_) => self.key_events(Key::KEY_RIGHT, &[]),
+            None => Vec::new(),
+        }
+    }
+
+    /// Remove the selected text from the entry, once the app replaced it.
+    fn drop_selection(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            self.entry.remove(start..end);
+            self.pos = start;
+            self.anchor = None;
+        }
+    }
+
+    /// End the selection before performing a non selecting `action`.
+    ///
+    /// Returns whether the action was performed on the selection: [`Action::Backspace`] and
+    /// [`Action::Delete`] delete it, [`Action::Left`] and [`Action::Right`] move the cursor to its
+    /// start and end. The inserting actions replace it, and the others unselect it.
+    fn end_selection(&mut self, action: Action) -> Result<bool, Box<dyn Error>> {
+        let Some((start, end)) = self.selection() else {
+            return Ok(false);
+        };
+        match action {
+            Action::SelectLeft
+            | Action::SelectRight
+            | Action::SelectHome
+            | Action::SelectEnd
+            | Action::SelectWordLeft
+            | Action::SelectWordRight
+            // the shortcuts can act on the selection, e.g. copy it
+            | Action::Pass
+            | Action::Block => return Ok(false),
+            Action::Backspace | Action::Delete => {
+                self.tap(Key::KEY_DELETE)?;
+                self.drop_selection();
+                return Ok(true);
+            }
+            Action::Left | Action::Right => {
+                let (key, pos) = match action {
+                    Action::Left => (Key::KEY_LEFT, start),
+                    _ => (Key::KEY_RIGHT, end),
+                };
+                self.tap(key)?;
+                self.pos = pos;
+                self.anchor = None;
+                return Ok(true);
+            }
+            Action::Newline | Action::Paste | Action::Yank => {
+                self.tap(Key::KEY_DELETE)?;
+                self.drop_selection();
+            }
+            _ => {
+                self.send_events(self.unselect_events())?;
+                self.anchor = None;
+            }
+        }
+        Ok(false)
+    }
+
+    /// Delete the whole entry, leaving the markers.
+    fn clear_entry(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        let mut events = self.end_events();
+        events.extend(
+            self.key_events(Key::KEY_BACKSPACE, &[])
+                .repeat(self.entry.len()),
+        );
+        self.send_events(events)?;
+        self.entry.clear();
+        self.pos = 0;
+        Ok(EventFlag::Block)
+    }
+
+    /// Move the cursor to the line above, or recall the previous entry of the history from the
+    /// first line.
+    fn up(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        if let Some(pos) = line_up(&self.entry, self.pos) {
+            self.move_to(pos)?;
+        } else if let Some(entry) = self.history.previous(&self.get_entry()) {
+            self.recall(&entry)?;
+        }
+        Ok(EventFlag::Block)
+    }
+
+    /// Move the cursor to the line below, or recall the next entry of the history from the last
+    /// line.
+    fn down(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        if let Some(pos) = line_down(&self.entry, self.pos) {
+            self.move_to(pos)?;
+        } else if let Some(entry) = self.history.next() {
+            self.recall(&entry)?;
+        }
+        Ok(EventFlag::Block)
+    }
+
+    /// Replace the entry with a recalled one, with the cursor at its end.
+    fn recall(&mut self, entry: &str) -> Result<(), Box<dyn Error>> {
+        let entry = Entry::new(entry);
+        let pos = entry.len();
+        self.replace_entry(entry, pos)
+    }
+
+    /// Start a reverse search of the history, or find the next older match.
+    fn reverse_search(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        match self.search.as_mut() {
+            Some((search, _)) => search.older(&self.history),
+            None => {
+                let original = Snapshot::new(&self.entry, self.pos);
+                self.search = Some((Search::default(), original));
+            }
+        }
+        self.render_search()?;
+        Ok(EventFlag::Block)
+    }
+
+    /// Type the search in place of the entry, as shiv has no window of its own.
+    fn render_search(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some((search, _)) = &self.search {
+            let (text, pos) = search.render(&self.history);
+            self.replace_entry(Entry::new(&text), pos)?;
+        }
+        Ok(())
+    }
+
+    /// End the search, restoring the entry it started from.
+    fn abort_search(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some((_, original)) = self.search.take() {
+            self.replace_entry(original.entry, original.pos)?;
+        }
+        Ok(())
+    }
+
+    /// End the search, replacing the entry with the match.
+    fn accept_search(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some((search, original)) = self.search.take() {
+            let entry = match search.matched(&self.history) {
+                Some(matched) => Entry::new(matched),
+                None => original.entry.clone(),
+            };
+            let pos = entry.len();
+            self.replace_entry(entry, pos)?;
+            self.record(Edit::Other, original);
+        }
+        Ok(())
+    }
+
+    /// Handle a key event during a history search.
+    ///
+    /// The typed text is added to the query. The editing actions accept the match, and return
+    /// `None` to be performed on it.
+    fn handle_search_key(
+        &mut self,
+        key: Key,
+        text: &KeyText,
+        modifiers: &HashMap<Key, Modifier>,
+    ) -> Result<Option<EventFlag>, Box<dyn Error>> {
+        let Some((search, _)) = self.search.as_mut() else {
+            return Ok(None);
+        };
+        match self.config.bindings.action(key, modifiers.values()) {
+            Some(Action::ReverseSearch) => return self.reverse_search().map(Some),
+            Some(Action::Cancel | Action::NormalMode) => self.abort_search()?,
+            Some(Action::Backspace) => {
+                search.pop(&self.history);
+                self.render_search()?;
+            }
+            Some(Action::Pass) => return Ok(Some(EventFlag::Emit)),
+            Some(Action::Block) => {}
+            Some(_) => {
+                self.accept_search()?;
+                return Ok(None);
+            }
+            None => match text {
+                KeyText::Text(text) | KeyText::Spacing(text)
+                    if text.chars().all(|c| !c.is_control()) =>
+                {
+                    search.push(&self.history, text);
+                    self.render_search()?;
+                }
+                _ => {
+                    if let Key::KEY_CAPSLOCK | Key::KEY_NUMLOCK = key {
+                        return Ok(Some(EventFlag::Emit));
+                    }
+                }
+            },
+        }
+        Ok(Some(EventFlag::Block))
+    }
+
+    /// Complete the word before the cursor.
+    ///
+    /// The candidates are listed in the background, by running the [`CompletionRequest`] taken
+    /// with [`Terminal::take_completion_request`], and inserted by [`Terminal::finish_completion`].
+    /// Repeated completions cycle through the candidates.
+    fn complete(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        if let Some(completion) = self.completion.as_mut() {
+            if self.last_action == Some(Action::Complete) {
+                completion.index = (completion.index + 1) % completion.candidates.len();
+                let (start, candidate) = (
+                    completion.start,
+                    completion.candidates[completion.index].clone(),
+                );
+                self.delete_to(start)?;
+                self.insert(&candidate)?;
+                return Ok(EventFlag::Block);
+            }
+        }
+        self.completion = None;
+        let Some(completer) = self.config.completer.clone() else {
+            return Ok(EventFlag::Block);
+        };
+        self.completions += 1;
+        self.pending_completion = Some(self.completions);
+        self.completion_request = Some(CompletionRequest {
+            id: self.completions,
+            line: self.entry[..self.pos].concat(),
+            completer,
+        });
+        Ok(EventFlag::Block)
+    }
+
+    /// Take the requested completion, whose candidates are to be listed in the background.
+    pub fn take_completion_request(&mut self) -> Option<CompletionRequest> {
+        self.completion_request.take()
+    }
+
+    /// Insert the candidates of a completion.
+    ///
+    /// A single candidate is inserted followed by a space, unless it is a directory. With several
+    /// candidates, the first one is inserted and the next completions cycle through them. The
+    /// candidates are dropped if another key was handled since the completion was requested.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The [`CompletionRequest`] the candidates were listed for.
+    /// * `candidates` - The candidates.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the event sending fails.
+    pub fn finish_completion(
+        &mut self,
+        request: &CompletionRequest,
+        candidates: Vec<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.pending_completion != Some(request.id)
+            || self.search.is_some()
+            || self.entry[..self.pos].concat() != request.line
+        {
+            log::debug!("Dropping the stale completion candidates");
+            return Ok(());
+        }
+        self.pending_completion = None;
+        log::debug!("Completion candidates: {:?}", candidates);
+        let before = Snapshot::new(&self.entry, self.pos);
+        let start = self.pos - Entry::new(split_word(&request.line).1).len();
+        match candidates.as_slice() {
+            [] => {}
+            [candidate] => {
+                let suffix = if candidate.ends_with('/') { "" } else { " " };
+                self.delete_to(start)?;
+                self.insert(&format!("{}{}", candidate, suffix))?;
+            }
+            [candidate, ..] => {
+                self.delete_to(start)?;
+                self.insert(candidate)?;
+                self.completion = Some(Completion {
+                    candidates,
+                    index: 0,
+                    start,
+                });
+            }
+        }
+        let changed = self.entry != before.entry;
+        self.record(Edit::Other, before);
+        if changed {
+            self.suggest()?;
+        }
+        Ok(())
+    }
+
+    /// Show the most recent entry of the history which starts like the entry, as a selected
+    /// suffix after the cursor, so that typing replaces it.
+    fn suggest(&mut self) -> Result<(), Box<dyn Error>> {
+        let normal_mode = self.vi.as_ref().is_some_and(|vi| vi.mode() == Mode::Normal);
+        if !self.config.autosuggest
+            || normal_mode
+            || self.search.is_some()
+            || self.anchor.is_some()
+            || self.pos != self.entry.len()
+        {
+            return Ok(());
+        }
+        let entry = self.get_entry();
+        let Some(suggestion) = self.history.suggest(&entry) else {
+            return Ok(());
+        };
+        // the suggestion must not join the last cluster of the entry, e.g. with a combining mark
+        let suggested = Entry::new(suggestion);
+        if !suggested.starts_with(&self.entry) {
+            return Ok(());
+        }
+        let ghost = suggested[self.entry.len()..].concat();
+        let n_ghost = suggested.len() - self.entry.len();
+        log::debug!("Suggestion: {:?}", suggestion);
+        self.type_text(&ghost)?;
+        let mut events = self.key_events(Key::KEY_LEFT, &[]).repeat(n_ghost);
+        events.extend(
+            self.key_events(Key::KEY_RIGHT, &[Key::KEY_LEFTSHIFT])
+                .repeat(n_ghost),
+        );
+        self.send_events(events)?;
+        self.ghost = ghost;
+        Ok(())
+    }
+
+    /// Remove the suggestion before handling a key which edits the entry or moves the cursor, and
+    /// accept it if the key is bound to [`Action::Right`] or [`Action::End`].
+    ///
+    /// The suggestion is kept for the shortcuts passed to the app and the keys without text, e.g.
+    /// Caps Lock, rather than being deleted and typed again.
+    fn handle_suggestion_key(
+        &mut self,
+        key: Key,
+        text: &KeyText,
+        modifiers: &HashMap<Key, Modifier>,
+    ) -> Result<Option<EventFlag>, Box<dyn Error>> {
+        if self.ghost.is_empty() {
+            return Ok(None);
         }
+        let action = self.action(key, modifiers);
+        let keeps_ghost = match action {
+            Some(action) => matches!(action, Action::Pass | Action::Block),
+            None => *text == KeyText::None,
+        };
+        if keeps_ghost {
+            return Ok(None);
+        }
+        // delete the selected suggestion
+        self.tap(Key::KEY_DELETE)?;
+        let ghost = std::mem::take(&mut self.ghost);
+        if !matches!(action, Some(Action::Right | Action::End)) {
+            return Ok(None);
+        }
+        let before = Snapshot::new(&self.entry, self.pos);
+        self.insert(&ghost)?;
+        self.record(Edit::Other, before);
+        self.last_action = action;
+        Ok(Some(EventFlag::Block))
+    }
+
+    /// Add the entry to the history, when it is submitted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the history can't be saved.
+    pub fn add_to_history(&mut self) -> Result<(), Box<dyn Error>> {
+        self.history.add(&self.get_entry())
+    }
+
+    /// Generate the events to insert a newline in the entry, Shift+Enter as Enter usually submits
+    /// the field.
+    fn newline_events(&self) -> Vec<InputEvent> {
+        self.key_events(Key::KEY_ENTER, &[Key::KEY_LEFTSHIFT])
+    }
+
+    fn newline(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        self.send_events(self.newline_events())?;
+        self.add_text("\n");
+        Ok(EventFlag::Block)
+    }
+
+    /// Add the clipboard's text to the entry, the chord is then sent for the app to paste it.
+    fn paste_clipboard(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        let text = match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+            Ok(text) => text.replace("\r\n", "\n"),
+            Err(e) => {
+                // the app could paste something else, e.g. an image
+                log::warn!("Failed to read the clipboard: {}", e);
+                return Ok(EventFlag::Block);
+            }
+        };
+        log::debug!("Pasting: {:?}", text);
+        self.add_text(&text);
+        Ok(EventFlag::Emit)
+    }
+
+    fn home(&mut self) -> Result<EventFlag, Box<dyn Error>> {
         // always block the event as we emulate the home by typing a bunch of left arrows
+        self.move_to(line_start(&self.entry, self.pos))?;
         Ok(EventFlag::Block)
     }
 
     fn end(&mut self) -> Result<EventFlag, Box<dyn Error>> {
-        if self.pos < self.entry.len() {
-            let right_events = self.end_events();
-            log::trace!("end right events: {:?}", right_events);
-            self.send_events(right_events)?;
-            self.pos = self.entry.len();
-        }
         // always block the event as we emulate the end by typing a bunch of right arrows
+        self.move_to(line_end(&self.entry, self.pos))?;
+        Ok(EventFlag::Block)
+    }
+
+    fn word_left(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        self.move_to(word_start(&self.entry, self.pos))?;
+        Ok(EventFlag::Block)
+    }
+
+    fn word_right(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        self.move_to(word_end(&self.entry, self.pos))?;
+        Ok(EventFlag::Block)
+    }
+
+    fn word_backspace(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        self.delete_to(word_start(&self.entry, self.pos))?;
+        Ok(EventFlag::Block)
+    }
+
+    fn word_delete(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        self.delete_to(word_end(&self.entry, self.pos))?;
+        Ok(EventFlag::Block)
+    }
+
+    /// Delete the characters between the cursor and `pos` into the kill ring.
+    fn kill_to(&mut self, pos: usize) -> Result<EventFlag, Box<dyn Error>> {
+        let (start, end) = (pos.min(self.pos), pos.max(self.pos));
+        if start == end {
+            return Ok(EventFlag::Block);
+        }
+        let text = self.entry[start..end].concat();
+        if self.last_action.is_some_and(|action| action.is_kill()) {
+            self.kill_ring.append(text, pos < self.pos);
+        } else {
+            self.kill_ring.push(text);
+        }
+        self.delete_to(pos)?;
+        Ok(EventFlag::Block)
+    }
+
+    fn yank(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        if let Some(text) = self.kill_ring.yank().map(str::to_string) {
+            let start = self.pos;
+            self.insert(&text)?;
+            self.yanked = Some((start, self.pos));
+        }
         Ok(EventFlag::Block)
     }
 
+    /// Replace the last yanked text with the previous entry of the kill ring.
+    fn yank_pop(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        let after_yank = matches!(self.last_action, Some(Action::Yank | Action::YankPop));
+        match self.yanked {
+            Some((start, end)) if after_yank && self.pos == end => {
+                if let Some(text) = self.kill_ring.yank_pop().map(str::to_string) {
+                    self.delete_to(start)?;
+                    self.insert(&text)?;
+                    self.yanked = Some((start, self.pos));
+                }
+            }
+            _ => {}
+        }
+        Ok(EventFlag::Block)
+    }
+
+    /// Swap the character before the cursor with the one under it, or the two before it at the
+    /// end of the entry, and move the cursor forward.
+    fn transpose_chars(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        if self.entry.len() < 2 || self.pos == 0 {
+            return Ok(EventFlag::Block);
+        }
+        let end = (self.pos + 1).min(self.entry.len());
+        let swapped = [&self.entry[end - 1], &self.entry[end - 2]]
+            .map(String::as_str)
+            .concat();
+        self.move_to(end)?;
+        self.delete_to(end - 2)?;
+        self.insert(&swapped)?;
+        Ok(EventFlag::Block)
+    }
+
+    /// Type `text` in the app, without adding it to the entry.
+    fn type_text(&self, text: &str) -> Result<(), Box<dyn Error>> {
+        for (i, line) in text.split('\n').enumerate() {
+            let newline = (i > 0).then(|| self.newline_events());
+            self.write_type(line.to_string(), newline)?;
+        }
+        Ok(())
+    }
+
+    /// Type `text` at the cursor.
+    fn insert(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        self.type_text(text)?;
+        self.add_text(text);
+        Ok(())
+    }
+
+    /// Replace the entry with `entry`, only retyping the characters which changed, and move the
+    /// cursor to `pos`.
+    fn replace_entry(&mut self, entry: Entry, pos: usize) -> Result<(), Box<dyn Error>> {
+        let (prefix, suffix) = common_affixes(&self.entry, &entry);
+        self.move_to(self.entry.len() - suffix)?;
+        self.delete_to(prefix)?;
+        let text = entry[prefix..entry.len() - suffix].concat();
+        if !text.is_empty() {
+            self.insert(&text)?;
+        }
+        self.move_to(pos)
+    }
+
+    /// Move the cursor to `pos` with arrow key presses.
+    fn move_to(&mut self, pos: usize) -> Result<(), Box<dyn Error>> {
+        let events = if pos < self.pos {
+            self.key_events(Key::KEY_LEFT, &[]).repeat(self.pos - pos)
+        } else {
+            self.key_events(Key::KEY_RIGHT, &[]).repeat(pos - self.pos)
+        };
+        log::trace!("Move events: {:?}", events);
+        self.send_events(events)?;
+        self.pos = pos;
+        Ok(())
+    }
+
+    /// Delete the characters between the cursor and `pos`, with backspace presses if `pos` is
+    /// before the cursor and delete presses otherwise.
+    fn delete_to(&mut self, pos: usize) -> Result<(), Box<dyn Error>> {
+        let events = if pos < self.pos {
+            self.key_events(Key::KEY_BACKSPACE, &[])
+                .repeat(self.pos - pos)
+        } else {
+            self.key_events(Key::KEY_DELETE, &[]).repeat(pos - self.pos)
+        };
+        log::trace!("Delete events: {:?}", events);
+        self.send_events(events)?;
+        let start = pos.min(self.pos);
+        self.entry.remove(start..pos.max(self.pos));
+        self.pos = start;
+        Ok(())
+    }
+
     fn end_events(&self) -> Vec<InputEvent> {
         let n_rights = self.entry.len() - self.pos;
-        self.key_events(Key::KEY_RIGHT, false).repeat(n_rights)
+        self.key_events(Key::KEY_RIGHT, &[]).repeat(n_rights)
     }
 
-    fn add_char(&mut self, c: char) {
-        self.entry.insert(self.pos, c);
-        self.pos += 1;
+    fn add_text(&mut self, text: &str) {
+        self.pos = self.entry.insert(self.pos, text);
     }
 
     fn get_entry(&self) -> String {
-        self.entry.iter().collect()
+        self.entry.to_string()
+    }
+
+    /// Get the [`Action`] bound to the `key` pressed with the `modifiers`.
+    ///
+    /// During a history search, [`Action::Cancel`] only aborts the search and isn't returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The pressed [`Key`].
+    /// * `modifiers` - The held modifier keys.
+    pub fn action(&self, key: Key, modifiers: &HashMap<Key, Modifier>) -> Option<Action> {
+        match self.config.bindings.action(key, modifiers.values()) {
+            // cancelling a history search only ends the search
+            Some(Action::Cancel) if self.search.is_some() => None,
+            action => action,
+        }
     }
 
     /// Handle a key event.
     ///
+    /// The key's bound [`Action`] is performed, [`Action::Submit`] and [`Action::Cancel`] are
+    /// left to the caller. Otherwise, the text it produces is added to the entry.
+    ///
     /// # Arguments
     ///
     /// * `key` - The [`Key`] that was pressed.
-    /// * `shift` - Whether the shift key was pressed.
+    /// * `text` - The [`KeyText`] the key produces in the user's layout.
+    /// * `modifiers` - The held modifier keys.
     ///
     /// # Errors
     ///
     /// This function will return an error if the event sending fails.
-    pub fn handle_key(&mut self, key: Key, shift: bool) -> Result<EventFlag, Box<dyn Error>> {
-        if let Some(c) = if shift {
-            SHIFT_KEY_TO_CHAR.get(&key)
-        } else {
-            KEY_TO_CHAR.get(&key)
-        } {
-            self.add_char(*c);
-            return Ok(EventFlag::Emit);
+    pub fn handle_key(
+        &mut self,
+        key: Key,
+        text: &KeyText,
+        modifiers: &HashMap<Key, Modifier>,
+    ) -> Result<EventFlag, Box<dyn Error>> {
+        if modifiers.contains_key(&key) {
+            // the modifiers are sent along with the keys they modify
+            return Ok(EventFlag::Block);
+        }
+        // the candidates of a pending completion would land wherever the key moved the cursor
+        if self.action(key, modifiers) != Some(Action::Complete) {
+            self.pending_completion = None;
+        }
+        if let Some(flag) = self.handle_suggestion_key(key, text, modifiers)? {
+            return Ok(flag);
+        }
+        let before = self.entry.clone();
+        let flag = self.edit(key, text, modifiers)?;
+        // the emitted keys are sent after, they would type over the suggestion
+        if let EventFlag::Block = flag {
+            if self.entry != before {
+                self.suggest()?;
+            }
+        }
+        Ok(flag)
+    }
+
+    /// Perform the key's bound [`Action`], or add the text it produces to the entry.
+    fn edit(
+        &mut self,
+        key: Key,
+        text: &KeyText,
+        modifiers: &HashMap<Key, Modifier>,
+    ) -> Result<EventFlag, Box<dyn Error>> {
+        if let Some(flag) = self.handle_search_key(key, text, modifiers)? {
+            return Ok(flag);
+        }
+        if let Some(action) = self.action(key, modifiers) {
+            let before = Snapshot::new(&self.entry, self.pos);
+            if self.end_selection(action)? {
+                self.record(Edit::Other, before);
+                self.last_action = Some(action);
+                return Ok(EventFlag::Block);
+            }
+            let flag = self.handle_action(action);
+            match action {
+                Action::Backspace => self.record(Edit::Backspace, before),
+                Action::Newline => self.record(Edit::Insert('\n'), before),
+                Action::Delete => self.record(Edit::Delete, before),
+                Action::Undo | Action::Redo => {}
+                _ => self.record(Edit::Other, before),
+            }
+            self.last_action = Some(action);
+            return flag;
+        }
+        self.last_action = None;
+        if self.vi.as_ref().is_some_and(|vi| vi.mode() == Mode::Normal) {
+            match text {
+                // the typed characters are commands in the normal mode
+                KeyText::Text(text) | KeyText::Spacing(text) => {
+                    self.send_events(self.unselect_events())?;
+                    self.anchor = None;
+                    for c in text.chars() {
+                        self.handle_vi_command(c)?;
+                    }
+                    return Ok(EventFlag::Block);
+                }
+                KeyText::Composing => return Ok(EventFlag::Block),
+                KeyText::None => {}
+            }
+        }
+        match text {
+            // let the app follow the sequence, the entry is updated once it completes
+            KeyText::Composing => Ok(EventFlag::Emit),
+            // control characters, e.g. from Enter or Backspace, are only handled as bindings
+            KeyText::Text(text) if text.chars().all(|c| !c.is_control() || c == '\t') => {
+                let before = Snapshot::new(&self.entry, self.pos);
+                // the typed text replaces the selection in the app
+                self.drop_selection();
+                self.add_text(text);
+                if let Some(c) = text.chars().next() {
+                    self.record(Edit::Insert(c), before);
+                }
+                // sent right away, so that the suggestion can follow it
+                self.send_key(key, modifiers)?;
+                Ok(EventFlag::Block)
+            }
+            // the key would start a sequence in the app, its text is typed instead
+            KeyText::Spacing(text) => {
+                let before = Snapshot::new(&self.entry, self.pos);
+                self.drop_selection();
+                self.insert(text)?;
+                if let Some(c) = text.chars().next() {
+                    self.record(Edit::Insert(c), before);
+                }
+                Ok(EventFlag::Block)
+            }
+            _ => match key {
+                // keep the app's locks in sync with ours
+                Key::KEY_CAPSLOCK | Key::KEY_NUMLOCK => Ok(EventFlag::Emit),
+                _ => Ok(EventFlag::Block),
+            },
+        }
+    }
+
+    /// Perform an editing [`Action`], by sending the equivalent key events to the app.
+    fn handle_action(&mut self, action: Action) -> Result<EventFlag, Box<dyn Error>> {
+        match action {
+            Action::Backspace => self.backspace(),
+            Action::Delete => self.delete(),
+            Action::Left => self.left(),
+            Action::Right => self.right(),
+            Action::Up => self.up(),
+            Action::Down => self.down(),
+            Action::Home => self.home(),
+            Action::End => self.end(),
+            Action::SelectLeft => self.select_to(self.pos.saturating_sub(1)),
+            Action::SelectRight => self.select_to((self.pos + 1).min(self.entry.len())),
+            Action::SelectHome => self.select_to(line_start(&self.entry, self.pos)),
+            Action::SelectEnd => self.select_to(line_end(&self.entry, self.pos)),
+            Action::SelectWordLeft => self.select_to(word_start(&self.entry, self.pos)),
+            Action::SelectWordRight => self.select_to(word_end(&self.entry, self.pos)),
+            Action::WordLeft => self.word_left(),
+            Action::WordRight => self.word_right(),
+            Action::WordBackspace => self.word_backspace(),
+            Action::WordDelete => self.word_delete(),
+            Action::KillLine => self.kill_to(line_end(&self.entry, self.pos)),
+            Action::UnixLineDiscard => self.kill_to(line_start(&self.entry, self.pos)),
+            Action::UnixWordRubout => self.kill_to(blank_word_start(&self.entry, self.pos)),
+            Action::KillWord => self.kill_to(word_end(&self.entry, self.pos)),
+            Action::BackwardKillWord => self.kill_to(word_start(&self.entry, self.pos)),
+            Action::Yank => self.yank(),
+            Action::YankPop => self.yank_pop(),
+            Action::TransposeChars => self.transpose_chars(),
+            Action::Newline => self.newline(),
+            Action::Paste => self.paste_clipboard(),
+            Action::ReverseSearch => self.reverse_search(),
+            Action::Complete => self.complete(),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::NormalMode => self.normal_mode(),
+            Action::Clear => self.clear_entry(),
+            // shortcuts don't edit the entry
+            Action::Pass => Ok(EventFlag::Emit),
+            // handled by the caller
+            Action::Submit | Action::Cancel | Action::Block | Action::None => Ok(EventFlag::Block),
+        }
+    }
+
+    /// Switch to the vi normal mode, or drop the pending operator if already in it.
+    fn normal_mode(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        if let Some(vi) = self.vi.as_mut() {
+            let mode = vi.mode();
+            vi.set_mode(Mode::Normal);
+            if mode != Mode::Normal {
+                self.show_mode(Mode::Normal)?;
+            }
+        }
+        Ok(EventFlag::Block)
+    }
+
+    /// Show the vi `mode` by replacing the marker before the entry, once switched to it.
+    fn show_mode(&self, mode: Mode) -> Result<(), Box<dyn Error>> {
+        let prompt = &self.config.prompt;
+        let (old, new) = match mode {
+            Mode::Insert => (&prompt.normal_prefix, &prompt.prefix),
+            Mode::Normal => (&prompt.prefix, &prompt.normal_prefix),
+        };
+        if old == new {
+            return Ok(());
+        }
+        let mut events = self.key_events(Key::KEY_LEFT, &[]).repeat(self.pos);
+        events.extend(
+            self.key_events(Key::KEY_BACKSPACE, &[])
+                .repeat(Entry::new(old).len()),
+        );
+        self.write_type(new.clone(), Some(events))?;
+        self.send_events(self.key_events(Key::KEY_RIGHT, &[]).repeat(self.pos))
+    }
+
+    /// Record the edit which changed the entry from the `before` state, or end the current undo
+    /// group if the entry didn't change.
+    fn record(&mut self, edit: Edit, before: Snapshot) {
+        if self.entry != before.entry {
+            self.undo.record(edit, before);
+        } else if self.pos != before.pos {
+            self.undo.seal();
+        }
+    }
+
+    /// Restore the entry as it was before the last group of edits.
+    fn undo(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        if let Some(snapshot) = self.undo.undo(Snapshot::new(&self.entry, self.pos)) {
+            self.replace_entry(snapshot.entry, snapshot.pos)?;
         }
-        match key {
-            Key::KEY_BACKSPACE => Ok(self.backspace()),
-            Key::KEY_DELETE => Ok(self.delete()),
-            Key::KEY_LEFT => Ok(self.left()),
-            Key::KEY_RIGHT => Ok(self.right()),
-            Key::KEY_END => self.end(),
-            Key::KEY_HOME => self.home(),
-            _ => Ok(EventFlag::Block),
+        Ok(EventFlag::Block)
+    }
+
+    /// Restore the entry as it was before the last undo.
+    fn redo(&mut self) -> Result<EventFlag, Box<dyn Error>> {
+        if let Some(snapshot) = self.undo.redo(Snapshot::new(&self.entry, self.pos)) {
+            self.replace_entry(snapshot.entry, snapshot.pos)?;
         }
+        Ok(EventFlag::Block)
+    }
+
+    /// Feed a character typed in the vi normal mode, and run the [`vi::Command`] it completes.
+    fn handle_vi_command(&mut self, c: char) -> Result<(), Box<dyn Error>> {
+        let Some(command) = self.vi.as_mut().and_then(|vi| vi.feed(c)) else {
+            return Ok(());
+        };
+        log::debug!("Vi command: {:?}", command);
+        let before = Snapshot::new(&self.entry, self.pos);
+        match command {
+            vi::Command::Move(motion) => {
+                self.move_to(vi::target(motion, &self.entry, self.pos))?;
+            }
+            vi::Command::Operate(operator, motion) => {
+                let target = vi::target(motion, &self.entry, self.pos);
+                let (start, end) = match motion {
+                    vi::Motion::Line => (0, self.entry.len()),
+                    _ => (target.min(self.pos), target.max(self.pos)),
+                };
+                // the kill ring doubles as the vi register
+                if start < end {
+                    self.kill_ring.push(self.entry[start..end].concat());
+                }
+                if operator != vi::Operator::Yank {
+                    self.move_to(end)?;
+                    self.delete_to(start)?;
+                }
+                self.move_to(start)?;
+            }
+            vi::Command::DeleteChar => {
+                if self.pos < self.entry.len() {
+                    self.kill_ring.push(self.entry[self.pos].clone());
+                    self.delete_to(self.pos + 1)?;
+                }
+            }
+            vi::Command::Put { before } => {
+                if let Some(text) = self.kill_ring.yank().map(str::to_string) {
+                    if !before {
+                        self.move_to((self.pos + 1).min(self.entry.len()))?;
+                    }
+                    self.insert(&text)?;
+                }
+            }
+            vi::Command::Undo => {
+                self.undo()?;
+            }
+            vi::Command::Insert(motion) => {
+                if let Some(motion) = motion {
+                    self.move_to(vi::target(motion, &self.entry, self.pos))?;
+                }
+            }
+        }
+        if command != vi::Command::Undo {
+            self.record(Edit::Other, before);
+        }
+        if self.vi.as_ref().is_some_and(|vi| vi.mode() == Mode::Insert) {
+            self.show_mode(Mode::Insert)?;
+        }
+        Ok(())
     }
 
     /// Run the command and return the stdout and stderr outputs.
@@ -408,13 +1271,13 @@ impl Terminal {
         &self,
         uid: u32,
     ) -> Result<tokio::process::Child, Box<dyn Error + Send + Sync>> {
-        let mut command = Command::new("sudo");
+        let args = [self.config.pre_cmd.as_slice(), &[self.get_entry()]].concat();
+        let mut command = Command::new(&args[0]);
         command
-            .args(["-u", &format!("#{}", uid), "-i", "--"])
-            .args(&self.config.pre_cmd)
-            .arg(self.get_entry())
+            .args(&args[1..])
             .stderr(Stdio::piped())
             .stdout(Stdio::piped());
+        permissions::run_as_user(command.as_std_mut(), uid).map_err(|e| e.to_string())?;
         log::info!("Running command: {:?}", &command);
         command.spawn().map_err(|e| e.into())
         // let output = command.output()?;
@@ -433,22 +1296,27 @@ impl Terminal {
         let out = self.send_events(self.clear_events());
         self.pos = 0;
         self.entry.clear();
+        self.ghost.clear();
+        self.anchor = None;
         out
     }
 
     /// Generate the clear events.
     pub fn clear_events(&self) -> Vec<InputEvent> {
-        // The delete events
-        // +1 for the < char
-        let n_to_right = self.entry.len() - self.pos + 1;
-        let mut events = self.key_events(Key::KEY_DELETE, false).repeat(n_to_right);
+        // The delete events, the newlines of the entry are deleted like any character
+        // + the suffix, and 1 for the selected suggestion if any
+        let n_to_right = self.entry.len() - self.pos
+            + Entry::new(&self.config.prompt.suffix).len()
+            + usize::from(!self.ghost.is_empty());
+        let mut events = self.unselect_events();
+        events.extend(self.key_events(Key::KEY_DELETE, &[]).repeat(n_to_right));
 
         // The backspace events
-        // + 1 for the > chars
+        // + the prefix
         events.extend_from_slice(
             &self
-                .key_events(Key::KEY_BACKSPACE, false)
-                .repeat(self.pos + 1),
+                .key_events(Key::KEY_BACKSPACE, &[])
+                .repeat(self.pos + Entry::new(self.prefix()).len()),
         );
         events
     }
@@ -477,6 +1345,9 @@ impl Terminal {
 
     /// Write the command output through the virtual device by sending the right key events.
     ///
+    /// The characters the layout can't type are written using the configured
+    /// [`UnicodeFallback`].
+    ///
     /// # Arguments
     ///
     /// * `contents`: The contents of the command output.
@@ -491,19 +1362,73 @@ impl Terminal {
         prev_events: Option<Vec<InputEvent>>,
     ) -> Result<(), Box<dyn Error>> {
         let mut events = prev_events.unwrap_or_default();
+        // toggle caps lock off while typing, the char map assumes no lock is active
+        let caps_lock = self
+            .caps_lock
+            .map(|key| self.key_events(key, &[]))
+            .unwrap_or_default();
+        events.extend_from_slice(&caps_lock);
 
+        // the characters to paste, for the paste fallback
+        let mut segment = String::new();
         for c in contents.chars() {
-            if let Some((key, shift)) = CHAR_TO_KEY.get(&c) {
-                events.extend_from_slice(&self.key_events(*key, *shift));
-            } else {
-                log::warn!("No key found for char: {}", c);
+            if let Some(char_events) = self.char_events(c) {
+                if !segment.is_empty() {
+                    self.paste(std::mem::take(&mut segment), std::mem::take(&mut events))?;
+                    std::thread::sleep(PASTE_DELAY);
+                }
+                events.extend(char_events);
+                continue;
+            }
+            match self.config.unicode_fallback {
+                UnicodeFallback::CtrlShiftU => match self.unicode_events(c) {
+                    Some(unicode_events) => events.extend(unicode_events),
+                    None => log::warn!("Can't type the code point of char: {}", c),
+                },
+                UnicodeFallback::Remap => {
+                    self.send_events(std::mem::take(&mut events))?;
+                    self.type_remapped(c).unwrap_or_else(|e| {
+                        log::warn!("Failed to type char {} with a remapped key: {}", c, e);
+                    });
+                }
+                UnicodeFallback::Paste => segment.push(c),
             }
         }
+        if !segment.is_empty() {
+            self.paste(segment, std::mem::take(&mut events))?;
+        }
+        events.extend(caps_lock);
         log::trace!("Write events: {:?}", events);
 
         self.send_events(events)
     }
 
+    /// Generate the events to enter the code point of `c` after Ctrl+Shift+U, if all the
+    /// hexadecimal digits are in the layout.
+    fn unicode_events(&self, c: char) -> Option<Vec<InputEvent>> {
+        let u = self.char_map.get(&'u')?;
+        let mut events = self.key_events(u.key, &[Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT]);
+        for digit in format!("{:x}", c as u32).chars().chain([' ']) {
+            events.extend(self.char_events(digit)?);
+        }
+        Some(events)
+    }
+
+    /// Type `c` by temporarily mapping the spare key to it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is no spare key or `xmodmap` fails.
+    fn type_remapped(&self, c: char) -> Result<(), Box<dyn Error>> {
+        let key = self.spare_key.ok_or("No spare key to remap")?;
+        let keycode = key.code() + 8;
+        xmodmap(&format!("keycode {} = U{:04X}", keycode, c as u32))?;
+        self.send_events(self.key_events(key, &[]))?;
+        // let the key press through before restoring the mapping
+        std::thread::sleep(REMAP_DELAY);
+        xmodmap(&format!("keycode {} =", keycode))
+    }
+
     /// Write the command output through the clipboard.
     ///
     /// # Arguments
@@ -515,9 +1440,12 @@ impl Terminal {
         contents: String,
         prev_events: Option<Vec<InputEvent>>,
     ) -> Result<(), Box<dyn Error>> {
-        let mut events = prev_events.unwrap_or_default();
+        self.paste(contents, prev_events.unwrap_or_default())
+    }
 
-        events.extend_from_slice(&self.key_events(Key::KEY_PASTE, false));
+    /// Put `contents` in the clipboard, then send the `events` followed by a paste.
+    fn paste(&self, contents: String, mut events: Vec<InputEvent>) -> Result<(), Box<dyn Error>> {
+        events.extend_from_slice(&self.key_events(Key::KEY_PASTE, &[]));
         log::trace!("Paste events: {:?}", events);
 
         let mut clipboard = arboard::Clipboard::new()?;
@@ -557,3 +1485,25 @@ impl Terminal {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_common_affixes() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(
+            common_affixes(&chars("echo foo"), &chars("echo bar")),
+            (5, 0)
+        );
+        assert_eq!(
+            common_affixes(&chars("echo foo"), &chars("cat foo")),
+            (0, 4)
+        );
+        assert_eq!(common_affixes(&chars("ls -la"), &chars("ls -a")), (4, 1));
+        assert_eq!(common_affixes(&chars("aaa"), &chars("aa")), (2, 0));
+        assert_eq!(common_affixes(&chars(""), &chars("foo")), (0, 0));
+        assert_eq!(common_affixes(&chars("foo"), &chars("foo")), (3, 0));
+    }
+}
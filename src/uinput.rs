@@ -29,7 +29,8 @@ pub fn create_uinput_device() -> Result<VirtualDevice, Box<dyn std::error::Error
     Ok(device)
 }
 
-fn get_all_keys() -> Vec<Key> {
+/// Get all the keys supported by the virtual device.
+pub fn get_all_keys() -> Vec<Key> {
     vec![
         evdev::Key::KEY_RESERVED,
         evdev::Key::KEY_ESC,
@@ -0,0 +1,189 @@
+use crate::entry::Entry;
+
+/// Maximum number of undo steps to keep.
+const MAX_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The state of the entry, to restore it on undo or redo.
+pub struct Snapshot {
+    pub entry: Entry,
+    pub pos: usize,
+}
+
+impl Snapshot {
+    /// Create a new [`Snapshot`].
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The [`Entry`].
+    /// * `pos` - The position of the cursor.
+    pub fn new(entry: &Entry, pos: usize) -> Snapshot {
+        Snapshot {
+            entry: entry.clone(),
+            pos,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The kinds of edits, consecutive edits of the same kind are undone together.
+pub enum Edit {
+    /// A typed character.
+    Insert(char),
+    /// A character deleted with backspace.
+    Backspace,
+    /// A character deleted with delete.
+    Delete,
+    /// Any other edit, e.g. a kill, which is undone on its own.
+    Other,
+}
+
+impl Edit {
+    /// Whether the edit belongs to the same undo group as the `previous` one.
+    ///
+    /// The typed characters are grouped by word like in readline: a group ends with the blanks
+    /// following a word.
+    fn continues(&self, previous: Edit) -> bool {
+        match (previous, *self) {
+            (Edit::Insert(previous), Edit::Insert(c)) => {
+                !previous.is_whitespace() || c.is_whitespace()
+            }
+            (Edit::Backspace, Edit::Backspace) | (Edit::Delete, Edit::Delete) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+/// The undo and redo stacks of the entry.
+pub struct UndoStack {
+    /// The states before the undoable edits, the most recent last.
+    undos: Vec<Snapshot>,
+    /// The states undone, the most recently undone last.
+    redos: Vec<Snapshot>,
+    /// The last recorded edit, while more edits can join its group.
+    group: Option<Edit>,
+}
+
+impl UndoStack {
+    /// Record an edit, unless it joins the group of the previous one.
+    ///
+    /// # Arguments
+    ///
+    /// * `edit` - The kind of [`Edit`].
+    /// * `before` - The [`Snapshot`] of the entry before the edit.
+    pub fn record(&mut self, edit: Edit, before: Snapshot) {
+        self.redos.clear();
+        if !self.group.is_some_and(|group| edit.continues(group)) {
+            self.undos.push(before);
+            if self.undos.len() > MAX_ENTRIES {
+                self.undos.remove(0);
+            }
+        }
+        self.group = Some(edit);
+    }
+
+    /// End the current group, e.g. when the cursor moves.
+    pub fn seal(&mut self) {
+        self.group = None;
+    }
+
+    /// Get the state to restore to undo the last group of edits.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - The current [`Snapshot`], restored by the next redo.
+    pub fn undo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        self.seal();
+        let snapshot = self.undos.pop()?;
+        self.redos.push(current);
+        Some(snapshot)
+    }
+
+    /// Get the state to restore to redo the last undone group of edits.
+    ///
+    /// # Arguments
+    ///
+    /// * `current` - The current [`Snapshot`], restored by the next undo.
+    pub fn redo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        self.seal();
+        let snapshot = self.redos.pop()?;
+        self.undos.push(current);
+        Some(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Type `text` at the end of `entry`, recording the edits.
+    fn type_text(stack: &mut UndoStack, entry: &mut Entry, text: &str) {
+        for c in text.chars() {
+            stack.record(Edit::Insert(c), Snapshot::new(entry, entry.len()));
+            entry.insert(entry.len(), &c.to_string());
+        }
+    }
+
+    fn snapshot(text: &str) -> Snapshot {
+        let entry = Entry::new(text);
+        let pos = entry.len();
+        Snapshot::new(&entry, pos)
+    }
+
+    #[test]
+    fn test_undo_groups() {
+        let mut stack = UndoStack::default();
+        let mut entry = Entry::default();
+        type_text(&mut stack, &mut entry, "echo  foo bar");
+        assert_eq!(
+            stack.undos,
+            [snapshot(""), snapshot("echo  "), snapshot("echo  foo ")]
+        );
+
+        // deletions are grouped apart from the insertions
+        for _ in 0..2 {
+            stack.record(Edit::Backspace, Snapshot::new(&entry, entry.len()));
+            entry.remove(entry.len() - 1..entry.len());
+        }
+        assert_eq!(stack.undos.last(), Some(&snapshot("echo  foo bar")));
+        type_text(&mut stack, &mut entry, "az");
+        assert_eq!(stack.undos.last(), Some(&snapshot("echo  foo b")));
+
+        // moving the cursor ends the group
+        stack.seal();
+        type_text(&mut stack, &mut entry, "z");
+        assert_eq!(stack.undos.last(), Some(&snapshot("echo  foo baz")));
+
+        stack.record(Edit::Other, snapshot("echo  foo bazz"));
+        stack.record(Edit::Other, snapshot(""));
+        assert_eq!(stack.undos.len(), 8);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut stack = UndoStack::default();
+        assert_eq!(stack.undo(snapshot("")), None);
+
+        let mut entry = Entry::default();
+        type_text(&mut stack, &mut entry, "ls foo");
+        assert_eq!(stack.undo(snapshot("ls foo")), Some(snapshot("ls ")));
+        assert_eq!(stack.undo(snapshot("ls ")), Some(snapshot("")));
+        assert_eq!(stack.undo(snapshot("")), None);
+        assert_eq!(stack.redo(snapshot("")), Some(snapshot("ls ")));
+        assert_eq!(stack.redo(snapshot("ls ")), Some(snapshot("ls foo")));
+        assert_eq!(stack.redo(snapshot("ls foo")), None);
+
+        // a new edit drops the redos
+        assert_eq!(stack.undo(snapshot("ls foo")), Some(snapshot("ls ")));
+        stack.record(Edit::Insert('x'), snapshot("ls "));
+        assert_eq!(stack.redo(snapshot("ls x")), None);
+        // and doesn't join the group before the undo
+        assert_eq!(stack.undo(snapshot("ls x")), Some(snapshot("ls ")));
+
+        for _ in 0..MAX_ENTRIES + 1 {
+            stack.record(Edit::Other, snapshot("x"));
+        }
+        assert_eq!(stack.undos.len(), MAX_ENTRIES);
+    }
+}
@@ -1,4 +1,7 @@
 use evdev::{Device, Key};
+use nix::unistd::{Uid, User};
+
+use std::{error::Error, path::PathBuf};
 
 use crate::uinput;
 /// Determine if a device is a keyboard.
@@ -9,7 +12,7 @@ use crate::uinput;
 pub fn check_device_is_keyboard(device: &Device) -> bool {
     if device
         .supported_keys()
-        .map_or(false, |keys| keys.contains(Key::KEY_ENTER))
+        .is_some_and(|keys| keys.contains(Key::KEY_ENTER))
     {
         if device.name() == Some(uinput::UINPUT_DEVICE_NAME) {
             false
@@ -22,17 +25,11 @@ pub fn check_device_is_keyboard(device: &Device) -> bool {
     }
 }
 
-/// Get a list of all keyboards.
-fn get_keyboards() -> Vec<Device> {
-    evdev::enumerate()
-        .map(|(_, device)| device)
-        .filter(check_device_is_keyboard)
-        .collect()
-}
-
-/// Release the keyboards.
-pub fn release_keyboards() {
-    get_keyboards().into_iter().for_each(|mut device| {
-        let _ = device.ungrab();
-    });
+/// Get the home directory of a user.
+///
+/// # Arguments
+///
+/// * `uid` - The UID of the user.
+pub fn get_home_dir(uid: u32) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    Ok(User::from_uid(Uid::from_raw(uid))?.map(|user| user.dir))
 }
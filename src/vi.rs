@@ -0,0 +1,337 @@
+use serde::Deserialize;
+
+use crate::entry::{line_end, line_start};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// The editing mode of the entry.
+pub enum EditingMode {
+    /// The readline like bindings.
+    #[default]
+    Emacs,
+    /// A modal vi like mode, on top of the bindings.
+    Vi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The modes of the vi editing mode.
+pub enum Mode {
+    Insert,
+    Normal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where a vi command moves the cursor to.
+pub enum Motion {
+    /// `h`
+    Left,
+    /// `l`
+    Right,
+    /// `w`, the start of the next word.
+    WordForward,
+    /// `b`, the start of the previous word.
+    WordBackward,
+    /// The end of the word under the cursor, used by `cw`.
+    WordEnd,
+    /// `0`, the start of the line.
+    LineStart,
+    /// `$`, the end of the line.
+    LineEnd,
+    /// The whole entry, used by `dd`, `cc` and `yy`.
+    Line,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The vi operators, applied to the text covered by a [`Motion`].
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A complete vi normal mode command.
+pub enum Command {
+    /// Move the cursor.
+    Move(Motion),
+    /// Apply the operator between the cursor and the motion's target.
+    Operate(Operator, Motion),
+    /// `x`
+    DeleteChar,
+    /// `p` and `P`, put the last deleted or yanked text after or before the cursor.
+    Put { before: bool },
+    /// `u`
+    Undo,
+    /// `i`, `a`, `A` and `I`, move the cursor and enter insert mode.
+    Insert(Option<Motion>),
+}
+
+#[derive(Debug, Clone)]
+/// The state of the vi editing mode.
+pub struct Vi {
+    mode: Mode,
+    /// The operator waiting for its motion, e.g. after `d`.
+    pending: Option<Operator>,
+}
+
+impl Default for Vi {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Insert,
+            pending: None,
+        }
+    }
+}
+
+impl Vi {
+    /// The current [`Mode`].
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switch to the `mode`, dropping any pending operator.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.pending = None;
+    }
+
+    /// Feed a character typed in normal mode.
+    ///
+    /// Returns the [`Command`] it completes, if any. The commands which insert text switch to
+    /// insert mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `c` - The typed character.
+    pub fn feed(&mut self, c: char) -> Option<Command> {
+        let motion = match c {
+            'h' => Some(Motion::Left),
+            'l' => Some(Motion::Right),
+            'w' => Some(Motion::WordForward),
+            'b' => Some(Motion::WordBackward),
+            '0' => Some(Motion::LineStart),
+            '$' => Some(Motion::LineEnd),
+            _ => None,
+        };
+        let command = if let Some(operator) = self.pending.take() {
+            // `cw` changes up to the end of the word, like `ce`
+            let motion = match (operator, c) {
+                (Operator::Delete, 'd') | (Operator::Change, 'c') | (Operator::Yank, 'y') => {
+                    Some(Motion::Line)
+                }
+                (Operator::Change, 'w') => Some(Motion::WordEnd),
+                _ => motion,
+            };
+            // an invalid motion cancels the operator
+            motion.map(|motion| Command::Operate(operator, motion))
+        } else {
+            match c {
+                'd' => self.pend(Operator::Delete),
+                'c' => self.pend(Operator::Change),
+                'y' => self.pend(Operator::Yank),
+                'D' => Some(Command::Operate(Operator::Delete, Motion::LineEnd)),
+                'C' => Some(Command::Operate(Operator::Change, Motion::LineEnd)),
+                'x' => Some(Command::DeleteChar),
+                'p' => Some(Command::Put { before: false }),
+                'P' => Some(Command::Put { before: true }),
+                'u' => Some(Command::Undo),
+                'i' => Some(Command::Insert(None)),
+                'a' => Some(Command::Insert(Some(Motion::Right))),
+                'A' => Some(Command::Insert(Some(Motion::LineEnd))),
+                'I' => Some(Command::Insert(Some(Motion::LineStart))),
+                _ => motion.map(Command::Move),
+            }
+        };
+        if let Some(Command::Insert(_) | Command::Operate(Operator::Change, _)) = command {
+            self.mode = Mode::Insert;
+        }
+        command
+    }
+
+    fn pend(&mut self, operator: Operator) -> Option<Command> {
+        self.pending = Some(operator);
+        None
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// The classes of characters which make up the vi words.
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+/// The class of the grapheme cluster `g`, judging by its base character.
+fn char_class(g: &str) -> CharClass {
+    let c = g.chars().next().unwrap_or(' ');
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// Find the position the `motion` moves the cursor to.
+///
+/// # Arguments
+///
+/// * `motion` - The [`Motion`] to apply.
+/// * `entry` - The grapheme clusters of the entry.
+/// * `pos` - The position of the cursor.
+pub fn target(motion: Motion, entry: &[String], pos: usize) -> usize {
+    let class = |i: usize| char_class(&entry[i]);
+    match motion {
+        Motion::Left => pos.saturating_sub(1),
+        Motion::Right => (pos + 1).min(entry.len()),
+        Motion::Line => 0,
+        Motion::LineStart => line_start(entry, pos),
+        Motion::LineEnd => line_end(entry, pos),
+        Motion::WordForward => {
+            let mut pos = pos;
+            if pos < entry.len() && class(pos) != CharClass::Blank {
+                let start = class(pos);
+                while pos < entry.len() && class(pos) == start {
+                    pos += 1;
+                }
+            }
+            while pos < entry.len() && class(pos) == CharClass::Blank {
+                pos += 1;
+            }
+            pos
+        }
+        Motion::WordBackward => {
+            let mut pos = pos;
+            while pos > 0 && class(pos - 1) == CharClass::Blank {
+                pos -= 1;
+            }
+            if pos > 0 {
+                let end = class(pos - 1);
+                while pos > 0 && class(pos - 1) == end {
+                    pos -= 1;
+                }
+            }
+            pos
+        }
+        Motion::WordEnd => {
+            let mut pos = pos;
+            while pos < entry.len() && class(pos) == CharClass::Blank {
+                pos += 1;
+            }
+            if pos < entry.len() {
+                let start = class(pos);
+                while pos < entry.len() && class(pos) == start {
+                    pos += 1;
+                }
+            }
+            pos
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::entry::Entry;
+
+    fn feed(vi: &mut Vi, keys: &str) -> Vec<Command> {
+        keys.chars().filter_map(|c| vi.feed(c)).collect()
+    }
+
+    #[test]
+    fn test_vi_commands() {
+        let mut vi = Vi::default();
+        assert_eq!(vi.mode(), Mode::Insert);
+        vi.set_mode(Mode::Normal);
+
+        assert_eq!(
+            feed(&mut vi, "hlwb0$"),
+            [
+                Motion::Left,
+                Motion::Right,
+                Motion::WordForward,
+                Motion::WordBackward,
+                Motion::LineStart,
+                Motion::LineEnd
+            ]
+            .map(Command::Move)
+        );
+        assert_eq!(
+            feed(&mut vi, "dwdbddy$yyxpPu"),
+            [
+                Command::Operate(Operator::Delete, Motion::WordForward),
+                Command::Operate(Operator::Delete, Motion::WordBackward),
+                Command::Operate(Operator::Delete, Motion::Line),
+                Command::Operate(Operator::Yank, Motion::LineEnd),
+                Command::Operate(Operator::Yank, Motion::Line),
+                Command::DeleteChar,
+                Command::Put { before: false },
+                Command::Put { before: true },
+                Command::Undo,
+            ]
+        );
+        assert_eq!(vi.mode(), Mode::Normal);
+
+        // invalid motions cancel the operator
+        assert_eq!(feed(&mut vi, "dzx"), [Command::DeleteChar]);
+        // unknown keys are ignored
+        assert_eq!(feed(&mut vi, "Z"), []);
+
+        assert_eq!(
+            feed(&mut vi, "cw"),
+            [Command::Operate(Operator::Change, Motion::WordEnd)]
+        );
+        assert_eq!(vi.mode(), Mode::Insert);
+
+        for (keys, motion) in [
+            ("i", None),
+            ("a", Some(Motion::Right)),
+            ("A", Some(Motion::LineEnd)),
+            ("I", Some(Motion::LineStart)),
+        ] {
+            vi.set_mode(Mode::Normal);
+            assert_eq!(feed(&mut vi, keys), [Command::Insert(motion)]);
+            assert_eq!(vi.mode(), Mode::Insert);
+        }
+
+        // switching modes drops the pending operator
+        vi.set_mode(Mode::Normal);
+        assert_eq!(feed(&mut vi, "d"), []);
+        vi.set_mode(Mode::Normal);
+        assert_eq!(feed(&mut vi, "w"), [Command::Move(Motion::WordForward)]);
+    }
+
+    #[test]
+    fn test_vi_targets() {
+        let entry = Entry::new("echo foo.bar  baz");
+        let len = entry.len();
+        assert_eq!(target(Motion::Left, &entry, 0), 0);
+        assert_eq!(target(Motion::Right, &entry, len), len);
+        assert_eq!(target(Motion::LineEnd, &entry, 3), len);
+        assert_eq!(target(Motion::Line, &entry, 3), 0);
+
+        assert_eq!(target(Motion::WordForward, &entry, 0), 5);
+        assert_eq!(target(Motion::WordForward, &entry, 5), 8);
+        assert_eq!(target(Motion::WordForward, &entry, 8), 9);
+        assert_eq!(target(Motion::WordForward, &entry, 9), 14);
+        assert_eq!(target(Motion::WordForward, &entry, 14), len);
+
+        assert_eq!(target(Motion::WordBackward, &entry, len), 14);
+        assert_eq!(target(Motion::WordBackward, &entry, 14), 9);
+        assert_eq!(target(Motion::WordBackward, &entry, 9), 8);
+        assert_eq!(target(Motion::WordBackward, &entry, 6), 5);
+        assert_eq!(target(Motion::WordBackward, &entry, 0), 0);
+
+        assert_eq!(target(Motion::WordEnd, &entry, 0), 4);
+        assert_eq!(target(Motion::WordEnd, &entry, 4), 8);
+        assert_eq!(target(Motion::WordEnd, &entry, 12), 17);
+
+        let entry = Entry::new("foo\nbar baz\n");
+        assert_eq!(target(Motion::LineStart, &entry, 9), 4);
+        assert_eq!(target(Motion::LineEnd, &entry, 5), 11);
+        assert_eq!(target(Motion::LineEnd, &entry, 12), 12);
+        assert_eq!(target(Motion::WordForward, &entry, 0), 4);
+    }
+}
@@ -0,0 +1,738 @@
+use evdev::Key;
+use serde::Deserialize;
+use xkbcommon_dl::{
+    keysyms, xkb_context_flags, xkb_key_direction, xkb_keymap, xkb_keymap_compile_flags,
+    xkb_rule_names, xkb_state, xkb_state_component, xkbcommon_option, XkbCommon, XKB_MOD_NAME_CAPS,
+    XKB_MOD_NAME_NUM,
+};
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    ffi::CString,
+    fmt::{Debug, Formatter},
+    ptr,
+};
+
+/// Location of the system wide keyboard configuration on Debian based distributions.
+const DEFAULT_KEYBOARD_PATH: &str = "/etc/default/keyboard";
+
+/// Offset between the evdev keycodes and the XKB keycodes.
+const EVDEV_OFFSET: u32 = 8;
+
+fn handle() -> Result<&'static XkbCommon, Box<dyn Error>> {
+    xkbcommon_option().ok_or_else(|| "Failed to load libxkbcommon, is it installed?".into())
+}
+
+fn keycode(key: Key) -> u32 {
+    key.code() as u32 + EVDEV_OFFSET
+}
+
+/// Get the keysym with the given name, e.g. `dead_acute` or `U00E9`.
+///
+/// # Arguments
+///
+/// * `name` - The name of the keysym.
+pub fn keysym_from_name(name: &str) -> Option<u32> {
+    let xkb = handle().ok()?;
+    let name = CString::new(name).ok()?;
+    let keysym = unsafe {
+        (xkb.xkb_keysym_from_name)(
+            name.as_ptr(),
+            xkbcommon_dl::xkb_keysym_flags::XKB_KEYSYM_NO_FLAGS,
+        )
+    };
+    // 0 is NoSymbol
+    (keysym != 0).then_some(keysym)
+}
+
+/// Get the character a keysym produces, if any.
+///
+/// # Arguments
+///
+/// * `keysym` - The keysym to convert.
+pub fn keysym_to_char(keysym: u32) -> Option<char> {
+    let xkb = handle().ok()?;
+    let code = unsafe { (xkb.xkb_keysym_to_utf32)(keysym) };
+    if code == 0 {
+        None
+    } else {
+        char::from_u32(code)
+    }
+}
+
+/// The keysym of `dead_grave`, the first of the dead keys in [`DEAD_KEY_CHARS`].
+const DEAD_GRAVE: u32 = 0xfe50;
+
+/// The characters the dead keys from `dead_grave` to `dead_ogonek` type on their own, as
+/// followed by a space in the Compose files.
+const DEAD_KEY_CHARS: [char; 13] = [
+    '`', '\'', '^', '~', '¯', '˘', '˙', '"', '°', '˝', 'ˇ', '¸', '˛',
+];
+
+/// Get the character a dead key types on its own, e.g. `^` for `dead_circumflex`.
+///
+/// # Arguments
+///
+/// * `keysym` - The keysym of the dead key.
+pub fn dead_key_char(keysym: u32) -> Option<char> {
+    let index = keysym.checked_sub(DEAD_GRAVE)?;
+    DEAD_KEY_CHARS.get(index as usize).copied()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A key along with the modifier keys to hold while pressing it.
+pub struct KeyCombo {
+    pub key: Key,
+    pub modifiers: Vec<Key>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// The XKB rules, model, layout, variant and options (RMLVO) used to compile a [`Keymap`].
+///
+/// Unset fields are left to libxkbcommon's defaults.
+pub struct RuleNames {
+    pub rules: Option<String>,
+    pub model: Option<String>,
+    pub layout: Option<String>,
+    pub variant: Option<String>,
+    pub options: Option<String>,
+}
+
+impl RuleNames {
+    /// Fill in the unset fields, first from the `XKB_DEFAULT_*` environment variables then from
+    /// `/etc/default/keyboard`.
+    pub fn resolve(self) -> RuleNames {
+        let default_keyboard = std::fs::read_to_string(DEFAULT_KEYBOARD_PATH)
+            .map(|contents| parse_default_keyboard(&contents))
+            .unwrap_or_default();
+        self.or(Self::from_env()).or(default_keyboard)
+    }
+
+    /// Read the names from the `XKB_DEFAULT_*` environment variables.
+    fn from_env() -> RuleNames {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        RuleNames {
+            rules: var("XKB_DEFAULT_RULES"),
+            model: var("XKB_DEFAULT_MODEL"),
+            layout: var("XKB_DEFAULT_LAYOUT"),
+            variant: var("XKB_DEFAULT_VARIANT"),
+            options: var("XKB_DEFAULT_OPTIONS"),
+        }
+    }
+
+    /// Fill in the unset fields with the ones of `other`.
+    ///
+    /// The variant only makes sense for the layout it was given with, so it is only taken from
+    /// `other` along with its layout.
+    pub fn or(self, other: RuleNames) -> RuleNames {
+        let (layout, variant) = if self.layout.is_some() {
+            (self.layout, self.variant)
+        } else {
+            (other.layout, other.variant)
+        };
+        RuleNames {
+            rules: self.rules.or(other.rules),
+            model: self.model.or(other.model),
+            layout,
+            variant,
+            options: self.options.or(other.options),
+        }
+    }
+}
+
+/// Parse the contents of a `/etc/default/keyboard` file.
+///
+/// # Arguments
+///
+/// * `contents` - The contents of the file.
+fn parse_default_keyboard(contents: &str) -> RuleNames {
+    let mut names = RuleNames::default();
+    for line in contents.lines() {
+        let Some((name, value)) = shlex::split(line)
+            .and_then(|words| words.into_iter().next())
+            .and_then(|word| {
+                word.split_once('=')
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+            })
+        else {
+            continue;
+        };
+        if value.is_empty() {
+            continue;
+        }
+        match name.as_str() {
+            "XKBMODEL" => names.model = Some(value),
+            "XKBLAYOUT" => names.layout = Some(value),
+            "XKBVARIANT" => names.variant = Some(value),
+            "XKBOPTIONS" => names.options = Some(value),
+            _ => {}
+        }
+    }
+    names
+}
+
+/// A compiled XKB keymap.
+pub struct Keymap {
+    keymap: *mut xkb_keymap,
+    xkb: &'static XkbCommon,
+}
+
+impl Debug for Keymap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Keymap {{ .. }}")
+    }
+}
+
+impl Keymap {
+    /// Compile a [`Keymap`] from the system's XKB data.
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The [`RuleNames`] to compile.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if libxkbcommon can't be loaded or the keymap fails to
+    /// compile.
+    pub fn from_names(names: &RuleNames) -> Result<Keymap, Box<dyn Error>> {
+        let xkb = handle()?;
+        let to_cstring = |value: &Option<String>| value.as_deref().map(CString::new).transpose();
+        let rules = to_cstring(&names.rules)?;
+        let model = to_cstring(&names.model)?;
+        let layout = to_cstring(&names.layout)?;
+        let variant = to_cstring(&names.variant)?;
+        let options = to_cstring(&names.options)?;
+        let as_ptr = |value: &Option<CString>| value.as_ref().map_or(ptr::null(), |v| v.as_ptr());
+        let rule_names = xkb_rule_names {
+            rules: as_ptr(&rules),
+            model: as_ptr(&model),
+            layout: as_ptr(&layout),
+            variant: as_ptr(&variant),
+            options: as_ptr(&options),
+        };
+
+        unsafe {
+            let context = (xkb.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return Err("Failed to create XKB context".into());
+            }
+            let keymap = (xkb.xkb_keymap_new_from_names)(
+                context,
+                &rule_names,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            );
+            (xkb.xkb_context_unref)(context);
+            Self::from_ptr(keymap, xkb)
+                .ok_or_else(|| format!("Failed to compile keymap from names: {:?}", names).into())
+        }
+    }
+
+    /// Load a [`Keymap`] from a keymap file in the XKB text format.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the keymap file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if libxkbcommon can't be loaded, the file can't be read
+    /// or the keymap fails to compile.
+    #[cfg(test)]
+    pub fn from_file(path: &std::path::Path) -> Result<Keymap, Box<dyn Error>> {
+        let xkb = handle()?;
+        let contents = CString::new(std::fs::read(path)?)?;
+
+        unsafe {
+            let context = (xkb.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return Err("Failed to create XKB context".into());
+            }
+            let keymap = (xkb.xkb_keymap_new_from_string)(
+                context,
+                contents.as_ptr(),
+                xkbcommon_dl::xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            );
+            (xkb.xkb_context_unref)(context);
+            Self::from_ptr(keymap, xkb)
+                .ok_or_else(|| format!("Failed to compile keymap file: {:?}", path).into())
+        }
+    }
+
+    /// Build the map of the characters the `keys` can produce to the [`KeyCombo`] producing them.
+    ///
+    /// Characters are reached through the Shift, level 3 (AltGr) and level 5 modifiers, the
+    /// combinations with the fewest modifiers are preferred.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keys which can be used, i.e. the ones the virtual device supports.
+    pub fn char_map(&self, keys: &[Key]) -> HashMap<char, KeyCombo> {
+        let level3 = self.level3_keys(keys).first().copied();
+        let level5 = self.level5_keys(keys).first().copied();
+        log::debug!("Level 3 key: {:?}, level 5 key: {:?}", level3, level5);
+
+        let mut combinations: Vec<Vec<Key>> = vec![vec![]];
+        for modifier in [Some(Key::KEY_LEFTSHIFT), level3, level5]
+            .into_iter()
+            .flatten()
+        {
+            let with_modifier: Vec<Vec<Key>> = combinations
+                .iter()
+                .map(|combination| [combination.as_slice(), &[modifier]].concat())
+                .collect();
+            combinations.extend(with_modifier);
+        }
+        combinations.sort_by_key(|combination| combination.len());
+
+        let mut map = HashMap::from([(
+            '\n',
+            KeyCombo {
+                key: Key::KEY_ENTER,
+                modifiers: vec![],
+            },
+        )]);
+        for modifiers in combinations {
+            let mut state = State::new(self);
+            modifiers.iter().for_each(|m| state.update_key(*m, true));
+            for key in keys {
+                let Some(c) = state.key_get_char(*key) else {
+                    continue;
+                };
+                if c.is_control() && c != '\t' {
+                    continue;
+                }
+                map.entry(c).or_insert_with(|| KeyCombo {
+                    key: *key,
+                    modifiers: modifiers.clone(),
+                });
+            }
+        }
+        map
+    }
+
+    /// Find the keys of `keys` which act as the level 3 shift, i.e. AltGr.
+    pub fn level3_keys(&self, keys: &[Key]) -> Vec<Key> {
+        self.keys_with_sym(keys, keysyms::ISO_Level3_Shift)
+    }
+
+    /// Find the keys of `keys` which act as the level 5 shift.
+    pub fn level5_keys(&self, keys: &[Key]) -> Vec<Key> {
+        self.keys_with_sym(keys, keysyms::ISO_Level5_Shift)
+    }
+
+    /// Find the keys of `keys` which toggle Caps Lock.
+    pub fn caps_lock_keys(&self, keys: &[Key]) -> Vec<Key> {
+        self.keys_with_sym(keys, keysyms::Caps_Lock)
+    }
+
+    /// Find the keys of `keys` which repeat when held, i.e. not the modifiers.
+    pub fn repeating_keys(&self, keys: &[Key]) -> Vec<Key> {
+        keys.iter()
+            .copied()
+            .filter(|key| unsafe {
+                (self.xkb.xkb_keymap_key_repeats)(self.keymap, keycode(*key)) != 0
+            })
+            .collect()
+    }
+
+    /// Find the keys of `keys` which aren't mapped to any keysym, and can be remapped to type
+    /// arbitrary characters.
+    ///
+    /// Only the keys with an X11 keycode, i.e. below 256, are considered.
+    pub fn unmapped_keys(&self, keys: &[Key]) -> Vec<Key> {
+        keys.iter()
+            .copied()
+            .filter(|key| *key != Key::KEY_RESERVED && keycode(*key) <= 255)
+            .filter(|key| self.first_sym(*key).is_none())
+            .collect()
+    }
+
+    /// Find the keys of `keys` with `keysym` on their first level.
+    fn keys_with_sym(&self, keys: &[Key], keysym: u32) -> Vec<Key> {
+        keys.iter()
+            .copied()
+            .filter(|key| self.first_sym(*key) == Some(keysym))
+            .collect()
+    }
+
+    /// Get the keysym on the first level of the `key`, if any.
+    fn first_sym(&self, key: Key) -> Option<u32> {
+        let mut syms = ptr::null();
+        let n_syms = unsafe {
+            (self.xkb.xkb_keymap_key_get_syms_by_level)(self.keymap, keycode(key), 0, 0, &mut syms)
+        };
+        (n_syms > 0).then(|| unsafe { *syms })
+    }
+
+    fn from_ptr(keymap: *mut xkb_keymap, xkb: &'static XkbCommon) -> Option<Keymap> {
+        if keymap.is_null() {
+            None
+        } else {
+            Some(Keymap { keymap, xkb })
+        }
+    }
+}
+
+impl Drop for Keymap {
+    fn drop(&mut self) {
+        unsafe { (self.xkb.xkb_keymap_unref)(self.keymap) }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+/// The lock modifiers, which are toggled rather than held.
+pub enum Lock {
+    Caps,
+    Num,
+}
+
+/// The state of a [`Keymap`], i.e. its active modifiers and layout.
+pub struct State {
+    state: *mut xkb_state,
+    xkb: &'static XkbCommon,
+    /// The modifier masks of the [`Lock::Caps`] and [`Lock::Num`] locks.
+    lock_masks: [u32; 2],
+}
+
+impl Debug for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "State {{ .. }}")
+    }
+}
+
+impl State {
+    /// Create a new [`State`] of the `keymap`, with no active modifiers.
+    ///
+    /// # Arguments
+    ///
+    /// * `keymap` - The [`Keymap`] to track the state of.
+    pub fn new(keymap: &Keymap) -> State {
+        let state = unsafe { (keymap.xkb.xkb_state_new)(keymap.keymap) };
+        // xkb_state_new only fails on allocation failure
+        assert!(!state.is_null(), "Failed to create XKB state");
+        let lock_masks = [XKB_MOD_NAME_CAPS, XKB_MOD_NAME_NUM].map(|name| {
+            let index =
+                unsafe { (keymap.xkb.xkb_keymap_mod_get_index)(keymap.keymap, name.as_ptr() as _) };
+            // the index is XKB_MOD_INVALID if the keymap doesn't have the modifier
+            1u32.checked_shl(index).unwrap_or(0)
+        });
+        State {
+            state,
+            xkb: keymap.xkb,
+            lock_masks,
+        }
+    }
+
+    fn lock_mask(&self, lock: Lock) -> u32 {
+        match lock {
+            Lock::Caps => self.lock_masks[0],
+            Lock::Num => self.lock_masks[1],
+        }
+    }
+
+    /// Whether the `lock` is active.
+    ///
+    /// # Arguments
+    ///
+    /// * `lock` - The [`Lock`] to check.
+    pub fn is_locked(&self, lock: Lock) -> bool {
+        let locked = unsafe {
+            (self.xkb.xkb_state_serialize_mods)(
+                self.state,
+                xkb_state_component::XKB_STATE_MODS_LOCKED,
+            )
+        };
+        locked & self.lock_mask(lock) != 0
+    }
+
+    /// Activate or deactivate the `lock`, e.g. to match the keyboard's LEDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `lock` - The [`Lock`] to update.
+    /// * `active` - Whether the lock is active.
+    pub fn set_lock(&mut self, lock: Lock, active: bool) {
+        let mods =
+            |component| unsafe { (self.xkb.xkb_state_serialize_mods)(self.state, component) };
+        let layout =
+            |component| unsafe { (self.xkb.xkb_state_serialize_layout)(self.state, component) };
+        let mut locked = mods(xkb_state_component::XKB_STATE_MODS_LOCKED);
+        if active {
+            locked |= self.lock_mask(lock);
+        } else {
+            locked &= !self.lock_mask(lock);
+        }
+        unsafe {
+            (self.xkb.xkb_state_update_mask)(
+                self.state,
+                mods(xkb_state_component::XKB_STATE_MODS_DEPRESSED),
+                mods(xkb_state_component::XKB_STATE_MODS_LATCHED),
+                locked,
+                layout(xkb_state_component::XKB_STATE_LAYOUT_DEPRESSED),
+                layout(xkb_state_component::XKB_STATE_LAYOUT_LATCHED),
+                layout(xkb_state_component::XKB_STATE_LAYOUT_LOCKED),
+            )
+        };
+    }
+
+    /// Update the state with a key press or release.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The [`Key`] which changed.
+    /// * `pressed` - Whether the key was pressed or released.
+    pub fn update_key(&mut self, key: Key, pressed: bool) {
+        let direction = if pressed {
+            xkb_key_direction::XKB_KEY_DOWN
+        } else {
+            xkb_key_direction::XKB_KEY_UP
+        };
+        unsafe { (self.xkb.xkb_state_update_key)(self.state, keycode(key), direction) };
+    }
+
+    /// Get the character the `key` produces in the current state, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The [`Key`] to look up.
+    pub fn key_get_char(&self, key: Key) -> Option<char> {
+        let code = unsafe { (self.xkb.xkb_state_key_get_utf32)(self.state, keycode(key)) };
+        if code == 0 {
+            None
+        } else {
+            char::from_u32(code)
+        }
+    }
+
+    /// Get the keysym the `key` produces in the current state, 0 if it doesn't produce exactly
+    /// one keysym.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The [`Key`] to look up.
+    pub fn key_get_one_sym(&self, key: Key) -> u32 {
+        unsafe { (self.xkb.xkb_state_key_get_one_sym)(self.state, keycode(key)) }
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        unsafe { (self.xkb.xkb_state_unref)(self.state) }
+    }
+}
+
+/// Load one of the keymaps in `tests/keymaps`.
+#[cfg(test)]
+pub fn test_keymap(name: &str) -> Keymap {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/keymaps")
+        .join(format!("{}.xkb", name));
+    Keymap::from_file(&path).expect("Failed to load test keymap")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn type_keys(state: &mut State, keys: &[Key], modifiers: &[Key]) -> String {
+        modifiers.iter().for_each(|m| state.update_key(*m, true));
+        let out = keys.iter().filter_map(|k| state.key_get_char(*k)).collect();
+        modifiers.iter().for_each(|m| state.update_key(*m, false));
+        out
+    }
+
+    #[test]
+    fn test_dead_key_char() {
+        let char_of = |name| dead_key_char(keysym_from_name(name).unwrap());
+        assert_eq!(char_of("dead_grave"), Some('`'));
+        assert_eq!(char_of("dead_circumflex"), Some('^'));
+        assert_eq!(char_of("dead_ogonek"), Some('˛'));
+        assert_eq!(char_of("dead_iota"), None);
+        assert_eq!(char_of("a"), None);
+    }
+
+    #[test]
+    fn test_decode_layouts() {
+        let keys = [Key::KEY_Q, Key::KEY_W, Key::KEY_Y, Key::KEY_Z, Key::KEY_1];
+        let mut us = State::new(&test_keymap("us"));
+        assert_eq!(type_keys(&mut us, &keys, &[]), "qwyz1");
+        assert_eq!(type_keys(&mut us, &keys, &[Key::KEY_LEFTSHIFT]), "QWYZ!");
+
+        let mut fr = State::new(&test_keymap("fr"));
+        assert_eq!(type_keys(&mut fr, &keys, &[]), "azyw&");
+        assert_eq!(type_keys(&mut fr, &keys, &[Key::KEY_LEFTSHIFT]), "AZYW1");
+
+        let mut de = State::new(&test_keymap("de"));
+        assert_eq!(type_keys(&mut de, &keys, &[]), "qwzy1");
+        assert_eq!(type_keys(&mut de, &keys, &[Key::KEY_RIGHTSHIFT]), "QWZY!");
+    }
+
+    #[test]
+    fn test_locks() {
+        let mut state = State::new(&test_keymap("us"));
+        assert!(!state.is_locked(Lock::Caps));
+        assert_eq!(
+            test_keymap("us").caps_lock_keys(&crate::uinput::get_all_keys()),
+            vec![Key::KEY_CAPSLOCK]
+        );
+        state.set_lock(Lock::Caps, true);
+        assert!(state.is_locked(Lock::Caps));
+        assert!(!state.is_locked(Lock::Num));
+        assert_eq!(type_keys(&mut state, &[Key::KEY_A, Key::KEY_1], &[]), "A1");
+        assert_eq!(
+            type_keys(&mut state, &[Key::KEY_A], &[Key::KEY_LEFTSHIFT]),
+            "a"
+        );
+
+        // the lock keys toggle the locks
+        type_keys(&mut state, &[], &[Key::KEY_CAPSLOCK]);
+        assert!(!state.is_locked(Lock::Caps));
+        assert_eq!(type_keys(&mut state, &[Key::KEY_KP1], &[]), "");
+        type_keys(&mut state, &[], &[Key::KEY_NUMLOCK]);
+        assert!(state.is_locked(Lock::Num));
+        assert_eq!(
+            type_keys(&mut state, &[Key::KEY_KP1, Key::KEY_KPDOT], &[]),
+            "1."
+        );
+
+        // setting a lock keeps the held modifiers
+        state.update_key(Key::KEY_LEFTSHIFT, true);
+        state.set_lock(Lock::Num, false);
+        assert_eq!(state.key_get_char(Key::KEY_A), Some('A'));
+    }
+
+    #[test]
+    fn test_repeating_keys() {
+        let keys = [
+            Key::KEY_A,
+            Key::KEY_BACKSPACE,
+            Key::KEY_LEFTSHIFT,
+            Key::KEY_CAPSLOCK,
+        ];
+        assert_eq!(
+            test_keymap("us").repeating_keys(&keys),
+            vec![Key::KEY_A, Key::KEY_BACKSPACE]
+        );
+    }
+
+    #[test]
+    fn test_unmapped_keys() {
+        let keys = crate::uinput::get_all_keys();
+        let unmapped = test_keymap("us").unmapped_keys(&keys);
+        assert!(!unmapped.is_empty());
+        assert!(!unmapped.contains(&Key::KEY_RESERVED));
+        assert!(!unmapped.contains(&Key::KEY_A));
+        assert!(unmapped.iter().all(|key| key.code() < 248));
+    }
+
+    fn combo(key: Key, modifiers: &[Key]) -> Option<KeyCombo> {
+        Some(KeyCombo {
+            key,
+            modifiers: modifiers.to_vec(),
+        })
+    }
+
+    #[test]
+    fn test_char_map() {
+        let keys = crate::uinput::get_all_keys();
+
+        let us = test_keymap("us").char_map(&keys);
+        assert_eq!(us.get(&'a').cloned(), combo(Key::KEY_A, &[]));
+        assert_eq!(
+            us.get(&'!').cloned(),
+            combo(Key::KEY_1, &[Key::KEY_LEFTSHIFT])
+        );
+        assert_eq!(
+            us.get(&'>').cloned(),
+            combo(Key::KEY_DOT, &[Key::KEY_LEFTSHIFT])
+        );
+        assert_eq!(us.get(&'\n').cloned(), combo(Key::KEY_ENTER, &[]));
+        assert_eq!(us.get(&'\t').cloned(), combo(Key::KEY_TAB, &[]));
+        assert_eq!(us.get(&'ä'), None);
+
+        let de = test_keymap("de").char_map(&keys);
+        assert_eq!(de.get(&'y').cloned(), combo(Key::KEY_Z, &[]));
+        assert_eq!(
+            de.get(&'>').cloned(),
+            combo(Key::KEY_102ND, &[Key::KEY_LEFTSHIFT])
+        );
+        assert_eq!(
+            de.get(&'@').cloned(),
+            combo(Key::KEY_Q, &[Key::KEY_RIGHTALT])
+        );
+        assert_eq!(
+            de.get(&'µ').cloned(),
+            combo(Key::KEY_M, &[Key::KEY_RIGHTALT])
+        );
+
+        let fr = test_keymap("fr").char_map(&keys);
+        assert_eq!(fr.get(&'a').cloned(), combo(Key::KEY_Q, &[]));
+        assert_eq!(
+            fr.get(&'1').cloned(),
+            combo(Key::KEY_1, &[Key::KEY_LEFTSHIFT])
+        );
+        assert_eq!(
+            fr.get(&'{').cloned(),
+            combo(Key::KEY_4, &[Key::KEY_RIGHTALT])
+        );
+
+        // neo's level 3 is on the backslash key and level 5 on the 102nd key
+        let neo = test_keymap("de-neo").char_map(&keys);
+        assert_eq!(
+            neo.get(&'?').cloned(),
+            combo(Key::KEY_H, &[Key::KEY_BACKSLASH])
+        );
+        assert_eq!(neo.get(&'£').cloned(), combo(Key::KEY_6, &[Key::KEY_102ND]));
+        assert_eq!(
+            neo.get(&'Σ').cloned(),
+            combo(Key::KEY_H, &[Key::KEY_BACKSLASH, Key::KEY_102ND])
+        );
+    }
+
+    #[test]
+    fn test_parse_default_keyboard() {
+        let contents = "# KEYBOARD CONFIGURATION FILE\n\
+                        XKBMODEL=\"pc105\"\n\
+                        XKBLAYOUT=\"de\"\n\
+                        XKBVARIANT=\"nodeadkeys\"\n\
+                        XKBOPTIONS=\"\"\n\
+                        \n\
+                        BACKSPACE=\"guess\"\n";
+        let names = parse_default_keyboard(contents);
+        assert_eq!(
+            names,
+            RuleNames {
+                rules: None,
+                model: Some("pc105".to_string()),
+                layout: Some("de".to_string()),
+                variant: Some("nodeadkeys".to_string()),
+                options: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rule_names_or() {
+        let config = RuleNames {
+            layout: Some("fr".to_string()),
+            ..Default::default()
+        };
+        let system = RuleNames {
+            model: Some("pc105".to_string()),
+            layout: Some("de".to_string()),
+            variant: Some("nodeadkeys".to_string()),
+            ..Default::default()
+        };
+        let names = config.or(system.clone());
+        assert_eq!(names.layout.as_deref(), Some("fr"));
+        assert_eq!(names.variant, None);
+        assert_eq!(names.model.as_deref(), Some("pc105"));
+
+        let names = RuleNames::default().or(system);
+        assert_eq!(names.layout.as_deref(), Some("de"));
+        assert_eq!(names.variant.as_deref(), Some("nodeadkeys"));
+    }
+}